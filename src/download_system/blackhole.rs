@@ -0,0 +1,185 @@
+use crate::{http::handlers::add_torrent, services::transmission::TorrentSource, AppData};
+use actix_web::web::Data;
+use anyhow::{bail, Context, Result};
+use log::{debug, error, info, warn};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tokio::{fs, time::sleep};
+
+/// Subfolder files are moved to once they were added to put.io.
+const PROCESSED_DIR: &str = ".processed";
+/// Subfolder files are moved to when they couldn't be added.
+const FAILED_DIR: &str = ".failed";
+/// Files modified more recently than this may still be being written.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+/// How many scans a file that put.io refuses is retried on before it's
+/// moved to the failed folder.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// What the scans remember about the files they've seen.
+#[derive(Default)]
+struct Seen {
+    /// Files added to put.io that couldn't be moved out of the way yet. They
+    /// are only moved on later scans, never added again.
+    added: HashSet<PathBuf>,
+    /// Failed attempts to add a file, by path.
+    attempts: HashMap<PathBuf, u32>,
+}
+
+/// Watches the configured blackhole directories for `.torrent` and `.magnet`
+/// files dropped by a Torrent Blackhole download client and adds them to
+/// put.io. A file dropped into a subfolder gets that subfolder's name as its
+/// category, so point each *arr's "Torrent Folder" at
+/// `<blackhole>/<category>` and its "Watch Folder" at
+/// `<download_directory>/<category>`.
+pub async fn watch(app_data: Data<AppData>) {
+    let interval = Duration::from_secs(app_data.config.polling_interval.max(1));
+    for dir in &app_data.config.blackhole_directories {
+        info!("Watching blackhole directory {}", dir);
+    }
+    let mut seen = Seen::default();
+    loop {
+        for dir in &app_data.config.blackhole_directories {
+            if let Err(e) = scan(&app_data, &mut seen, Path::new(dir)).await {
+                error!("blackhole {}: {:#}", dir, e);
+            }
+        }
+        sleep(interval).await;
+    }
+}
+
+/// Processes every settled file in `root` and its direct subfolders.
+async fn scan(app_data: &Data<AppData>, seen: &mut Seen, root: &Path) -> Result<()> {
+    process_dir(app_data, seen, root, root, "default").await?;
+
+    let mut entries = fs::read_dir(root)
+        .await
+        .with_context(|| format!("cannot read {}", root.display()))?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') || !entry.file_type().await?.is_dir() {
+            continue;
+        }
+        process_dir(app_data, seen, root, &entry.path(), &name).await?;
+    }
+    Ok(())
+}
+
+async fn process_dir(
+    app_data: &Data<AppData>,
+    seen: &mut Seen,
+    root: &Path,
+    dir: &Path,
+    category: &str,
+) -> Result<()> {
+    let mut entries = fs::read_dir(dir)
+        .await
+        .with_context(|| format!("cannot read {}", dir.display()))?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let metadata = entry.metadata().await?;
+        if !metadata.is_file() || !is_blackhole_file(&path) {
+            continue;
+        }
+        let settled = metadata
+            .modified()
+            .ok()
+            .and_then(|m| SystemTime::now().duration_since(m).ok())
+            .is_some_and(|age| age >= SETTLE_TIME);
+        if !settled {
+            debug!("blackhole: {} is still being written", path.display());
+            continue;
+        }
+
+        let outcome = if seen.added.contains(&path) {
+            PROCESSED_DIR
+        } else {
+            match add(app_data, &path, category).await {
+                Ok(_) => {
+                    info!(
+                        "blackhole: added {} (category: {})",
+                        path.display(),
+                        category
+                    );
+                    seen.attempts.remove(&path);
+                    seen.added.insert(path.clone());
+                    PROCESSED_DIR
+                }
+                Err(e) => {
+                    let attempts = seen.attempts.entry(path.clone()).or_default();
+                    *attempts += 1;
+                    warn!(
+                        "blackhole: failed to add {} (attempt {}/{}): {:#}",
+                        path.display(),
+                        attempts,
+                        MAX_ATTEMPTS,
+                        e
+                    );
+                    if *attempts < MAX_ATTEMPTS {
+                        continue;
+                    }
+                    seen.attempts.remove(&path);
+                    FAILED_DIR
+                }
+            }
+        };
+        match move_to(root, &path, outcome).await {
+            Ok(()) => {
+                seen.added.remove(&path);
+            }
+            // An added file stays in `seen.added` until it's out of the way,
+            // so it isn't added again; a failed one is simply retried.
+            Err(e) => error!(
+                "blackhole: cannot move {} to {}: {:#}",
+                path.display(),
+                outcome,
+                e
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn is_blackhole_file(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("torrent") | Some("magnet")
+    )
+}
+
+async fn add(app_data: &Data<AppData>, path: &Path, category: &str) -> Result<()> {
+    let bytes = fs::read(path).await?;
+    let source = if path.extension().is_some_and(|e| e == "magnet") {
+        let uri = String::from_utf8(bytes).context("magnet file is not valid UTF-8")?;
+        let uri = uri.trim();
+        if !uri.starts_with("magnet:") {
+            bail!("magnet file does not contain a magnet link");
+        }
+        TorrentSource::Url(uri.to_string())
+    } else {
+        TorrentSource::Metainfo(bytes)
+    };
+    add_torrent(
+        &app_data.config.putio.api_key,
+        &source,
+        category,
+        &[],
+        app_data,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Moves `path` to the same relative location under `root/subdir`.
+async fn move_to(root: &Path, path: &Path, subdir: &str) -> Result<()> {
+    let relative = path.strip_prefix(root)?;
+    let target: PathBuf = root.join(subdir).join(relative);
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::rename(path, &target).await?;
+    Ok(())
+}
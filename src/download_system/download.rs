@@ -1,15 +1,19 @@
 use super::transfer::{DownloadTarget, TargetType};
-use crate::AppData;
+use crate::{state::StateManager, AppData, Config};
 use actix_web::web::Data;
 use anyhow::{bail, Context, Result};
-use async_channel::{Receiver, Sender};
+use async_channel::Sender;
 use colored::*;
 use file_owner::PathExt;
 use futures::StreamExt;
 use log::{error, info, warn};
 use nix::unistd::Uid;
-use std::time::Duration;
-use std::{fs, path::Path};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, SeekFrom};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::{fmt, fs, path::Path};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 
 /// How long to wait for a download request to start returning a response (the
 /// connect + response-headers phase). Bounds it so a server that accepts the
@@ -20,31 +24,66 @@ const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
 /// as stalled (and erroring so the retry loop resumes).
 const STREAM_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
 
+/// How often a running download checks whether its transfer was stopped.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many times a stalled or failed stream is resumed before giving up.
+const MAX_ATTEMPTS: u32 = 20;
+
+/// How many times a file that fails verification is downloaded again.
+const MAX_VERIFY_ATTEMPTS: u32 = 3;
+
+/// How often a segmented download records its progress in the sidecar file.
+const SEGMENT_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Error returned when a download is aborted because its transfer was stopped
+/// through `torrent-stop`. Not retried.
+#[derive(Debug)]
+pub struct DownloadStopped;
+
+impl fmt::Display for DownloadStopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "transfer stopped")
+    }
+}
+
+impl std::error::Error for DownloadStopped {}
+
+/// Error a stream ends with when the schedule pauses downloading, so the
+/// connection isn't held open for the whole pause. The retry loops wait for
+/// the schedule and resume it; it doesn't count as a failed attempt.
+#[derive(Debug)]
+struct SchedulePaused;
+
+impl fmt::Display for SchedulePaused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "paused by schedule")
+    }
+}
+
+impl std::error::Error for SchedulePaused {}
+
 #[derive(Clone)]
 pub struct Worker {
     _id: usize,
     app_data: Data<AppData>,
-    drx: Receiver<DownloadTargetMessage>,
 }
 
 impl Worker {
-    pub fn start(id: usize, app_data: Data<AppData>, drx: Receiver<DownloadTargetMessage>) {
-        let s = Self {
-            _id: id,
-            app_data,
-            drx,
-        };
+    pub fn start(id: usize, app_data: Data<AppData>) {
+        let s = Self { _id: id, app_data };
 
         let _join_handle = actix_rt::spawn(async move { s.work().await });
     }
     async fn work(&self) -> Result<()> {
         loop {
             // Wait for a DownloadTarget
-            let dtm = self.drx.recv().await?;
+            let dtm = self.app_data.download_queue.pop().await;
 
             // Download the target
             let done_status = match download_target(&self.app_data, &dtm.download_target).await {
                 Ok(_) => DownloadDoneStatus::Success,
+                Err(e) if e.is::<DownloadStopped>() => DownloadDoneStatus::Stopped,
                 Err(_) => DownloadDoneStatus::Failed,
             };
             // Reporting status can fail if the orchestration worker that queued
@@ -71,12 +110,33 @@ async fn download_target(app_data: &Data<AppData>, target: &DownloadTarget) -> R
         TargetType::File => {
             // Delete file if already exists
             if !Path::new(&target.to).exists() {
+                if app_data.state.is_stopped(&target.transfer_hash).await {
+                    info!("{}: download {}", &target, "stopped".yellow());
+                    bail!(DownloadStopped);
+                }
+                if let Err(e) = wait_for_schedule(target, &app_data.state).await {
+                    info!("{}: download {}", &target, "stopped".yellow());
+                    return Err(e);
+                }
                 info!("{}: download {}", &target, "started".yellow());
-                match fetch(target, app_data.config.uid, &app_data.http).await {
+                let segments = segment_count(target, &app_data.config);
+                match fetch(
+                    target,
+                    segments,
+                    app_data.config.uid,
+                    &app_data.http,
+                    &app_data.state,
+                )
+                .await
+                {
                     Ok(_) => info!("{}: download {}", &target, "succeeded".green()),
+                    Err(e) if e.is::<DownloadStopped>() => {
+                        info!("{}: download {}", &target, "stopped".yellow());
+                        return Err(e);
+                    }
                     Err(e) => {
                         error!("{}: download {}: {}", &target, "failed".red(), e);
-                        bail!(e)
+                        return Err(e);
                     }
                 };
             } else {
@@ -87,7 +147,22 @@ async fn download_target(app_data: &Data<AppData>, target: &DownloadTarget) -> R
     Ok(())
 }
 
-async fn fetch(target: &DownloadTarget, uid: u32, client: &reqwest::Client) -> Result<()> {
+/// Number of byte ranges to fetch `target` in: 1 (a single stream) unless
+/// `download_segments` is set and the file is large enough to give every
+/// segment at least `min_segment_size_mb`.
+fn segment_count(target: &DownloadTarget, config: &Config) -> u64 {
+    let min_size = config.min_segment_size_mb.max(1) * 1024 * 1024;
+    let size = u64::try_from(target.size).unwrap_or(0);
+    config.download_segments.min(size / min_size).max(1)
+}
+
+async fn fetch(
+    target: &DownloadTarget,
+    segments: u64,
+    uid: u32,
+    client: &reqwest::Client,
+    state: &StateManager,
+) -> Result<()> {
     let tmp_path = format!("{}.downloading", &target.to);
 
     // Make sure the destination directory exists. A File target can be processed
@@ -98,21 +173,21 @@ async fn fetch(target: &DownloadTarget, uid: u32, client: &reqwest::Client) -> R
         tokio::fs::create_dir_all(parent).await?;
     }
 
-    // A single stream can stall (put.io stops sending mid-download). Rather than
-    // restarting the whole file, retry and resume from the bytes already on disk
-    // using a Range request. put.io serves `206 Partial Content`, so each retry
-    // picks up where the previous one stopped until the file is complete.
-    const MAX_ATTEMPTS: u32 = 20;
-    let mut attempt = 0;
+    // A truncated resume or a corrupted chunk would otherwise go straight to
+    // the *arr, so check the result and start over if it doesn't match.
+    let mut verify_attempt = 0;
     loop {
-        attempt += 1;
-        match fetch_attempt(target, &tmp_path, client).await {
+        verify_attempt += 1;
+        let hash = download(target, &tmp_path, segments, client, state).await?;
+        match verify(target, &tmp_path, hash).await {
             Ok(()) => break,
-            Err(e) if attempt < MAX_ATTEMPTS => {
-                warn!("{}: download attempt {} failed ({}), resuming", target, attempt, e);
-                tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+            Err(e) => {
+                tokio::fs::remove_file(&tmp_path).await?;
+                if verify_attempt >= MAX_VERIFY_ATTEMPTS {
+                    bail!("verification failed {} times: {}", verify_attempt, e);
+                }
+                warn!("{}: verification failed ({}), downloading again", target, e);
             }
-            Err(e) => bail!("download failed after {} attempts: {}", attempt, e),
         }
     }
 
@@ -121,10 +196,114 @@ async fn fetch(target: &DownloadTarget, uid: u32, client: &reqwest::Client) -> R
     }
 
     fs::rename(&tmp_path, &target.to)?;
+    state.record_file_downloaded();
 
     Ok(())
 }
 
+/// Downloads `target` into `tmp_path`. Returns the CRC32 computed while
+/// writing, if the whole file went through a single stream.
+async fn download(
+    target: &DownloadTarget,
+    tmp_path: &str,
+    segments: u64,
+    client: &reqwest::Client,
+    state: &StateManager,
+) -> Result<Option<StreamHash>> {
+    // Keep resuming a partial file the way it was started: segmented if it has
+    // segment state, otherwise (e.g. started before segments were enabled) as a
+    // single stream.
+    let segmented = target.size > 0
+        && (Path::new(&segments_path(tmp_path)).exists()
+            || (segments > 1 && !Path::new(tmp_path).exists()));
+    if segmented {
+        fetch_segmented(target, tmp_path, segments, client, state).await?;
+        return Ok(None);
+    }
+
+    // A single stream can stall (put.io stops sending mid-download). Rather than
+    // restarting the whole file, retry and resume from the bytes already on disk
+    // using a Range request. put.io serves `206 Partial Content`, so each retry
+    // picks up where the previous one stopped until the file is complete.
+    let mut hash = None;
+    let mut attempt = 0;
+    loop {
+        wait_for_schedule(target, state).await?;
+        match fetch_attempt(target, tmp_path, &mut hash, client, state).await {
+            Ok(()) => return Ok(hash),
+            Err(e) if e.is::<DownloadStopped>() => return Err(e),
+            Err(e) if e.is::<SchedulePaused>() => {}
+            Err(e) => {
+                attempt += 1;
+                if attempt >= MAX_ATTEMPTS {
+                    bail!("download failed after {} attempts: {}", attempt, e);
+                }
+                warn!(
+                    "{}: download attempt {} failed ({}), resuming",
+                    target, attempt, e
+                );
+                tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+            }
+        }
+    }
+}
+
+/// CRC32 of a file computed while writing it, with the number of bytes it
+/// covers, so a resumed stream can carry on hashing where the previous
+/// attempt stopped.
+#[derive(Default)]
+struct StreamHash {
+    hasher: crc32fast::Hasher,
+    bytes: u64,
+}
+
+/// Checks a downloaded file against the size and CRC32 put.io reports.
+/// `hash` is used when it covers the whole file; otherwise the file is read
+/// back to compute it.
+async fn verify(target: &DownloadTarget, tmp_path: &str, hash: Option<StreamHash>) -> Result<()> {
+    let len = tokio::fs::metadata(tmp_path).await?.len();
+    if target.size > 0 && len != target.size as u64 {
+        bail!("expected {} bytes, got {}", target.size, len);
+    }
+    let Some(expected) = target.crc32.as_deref() else {
+        return Ok(());
+    };
+    let Ok(expected) = u32::from_str_radix(expected, 16) else {
+        warn!("{}: ignoring invalid CRC32 {:?}", target, expected);
+        return Ok(());
+    };
+    let actual = match hash {
+        Some(hash) if hash.bytes == len => hash.hasher.finalize(),
+        _ => file_crc32(tmp_path).await?,
+    };
+    if actual != expected {
+        bail!(
+            "CRC32 mismatch: expected {:08x}, got {:08x}",
+            expected,
+            actual
+        );
+    }
+    Ok(())
+}
+
+async fn file_crc32(path: &str) -> Result<u32> {
+    let path = path.to_string();
+    tokio::task::spawn_blocking(move || {
+        let mut file = fs::File::open(&path)?;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut buf = vec![0; 1 << 20];
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        Ok(hasher.finalize())
+    })
+    .await?
+}
+
 /// Downloads `target` into `tmp_path`, resuming from whatever is already on disk
 /// via a Range request. Returns Ok only when the stream finished cleanly; a
 /// stall (no data for 60s) or a non-success status returns an error so the
@@ -132,7 +311,9 @@ async fn fetch(target: &DownloadTarget, uid: u32, client: &reqwest::Client) -> R
 async fn fetch_attempt(
     target: &DownloadTarget,
     tmp_path: &str,
+    hash: &mut Option<StreamHash>,
     client: &reqwest::Client,
+    state: &StateManager,
 ) -> Result<()> {
     let existing = tokio::fs::metadata(tmp_path)
         .await
@@ -144,20 +325,7 @@ async fn fetch_attempt(
     if existing > 0 {
         req = req.header(reqwest::header::RANGE, format!("bytes={}-", existing));
     }
-    // Bound the request itself, not just the connect. put.io can accept the
-    // connection and then stall before sending response headers; without this
-    // timeout `send()` blocks forever, parking the download worker (and, via the
-    // blocked done channel, every orchestration worker) until the whole process
-    // stops pulling. On timeout we error so the retry loop can resume (issue #32).
-    let response =
-        match tokio::time::timeout(REQUEST_TIMEOUT, req.send()).await {
-            Ok(r) => r?,
-            Err(_) => bail!(
-                "timed out after {:?} waiting for response headers from {}",
-                REQUEST_TIMEOUT,
-                target.from.as_deref().unwrap_or("<no url>")
-            ),
-        };
+    let response = send(req, target).await?;
     let status = response.status();
     if !(status.is_success() || status == reqwest::StatusCode::PARTIAL_CONTENT) {
         bail!("HTTP {}", status);
@@ -166,17 +334,48 @@ async fn fetch_attempt(
     // If the server honored the Range request (206), append to the partial file;
     // otherwise it returned the whole file (200), so start it over.
     let resumed = status == reqwest::StatusCode::PARTIAL_CONTENT && existing > 0;
+    if !resumed {
+        *hash = Some(StreamHash::default());
+    } else if hash.as_ref().is_some_and(|h| h.bytes != existing) {
+        // The hash no longer lines up with the file; verify reads it back.
+        *hash = None;
+    }
     let mut tmp_file = if resumed {
-        tokio::fs::OpenOptions::new().append(true).open(tmp_path).await?
+        tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(tmp_path)
+            .await?
     } else {
         tokio::fs::File::create(tmp_path).await?
     };
 
     let mut byte_stream = response.bytes_stream();
+    let mut last_stop_check = Instant::now();
     loop {
+        // Keep the partial file on a stop, so a later start resumes from it.
+        if last_stop_check.elapsed() >= STOP_CHECK_INTERVAL {
+            if state.is_stopped(&target.transfer_hash).await {
+                bail!(DownloadStopped);
+            }
+            if state.is_schedule_paused() {
+                // The next attempt resumes from the file's length.
+                tmp_file.flush().await?;
+                bail!(SchedulePaused);
+            }
+            last_stop_check = Instant::now();
+        }
         match tokio::time::timeout(STREAM_IDLE_TIMEOUT, byte_stream.next()).await {
             Ok(Some(item)) => {
-                tokio::io::copy(&mut item?.as_ref(), &mut tmp_file).await?;
+                let chunk = item?;
+                tokio::io::copy(&mut chunk.as_ref(), &mut tmp_file).await?;
+                if let Some(hash) = hash.as_mut() {
+                    hash.hasher.update(&chunk);
+                    hash.bytes += chunk.len() as u64;
+                }
+                state.record_downloaded_bytes(chunk.len() as u64);
+                state
+                    .throttle_download(&target.transfer_hash, chunk.len() as u64)
+                    .await;
             }
             Ok(None) => break,
             Err(_) => bail!("stalled: no data received for {:?}", STREAM_IDLE_TIMEOUT),
@@ -185,6 +384,283 @@ async fn fetch_attempt(
     Ok(())
 }
 
+/// Holds a download while the schedule pauses downloading. Fails with
+/// [`DownloadStopped`] if the transfer is stopped in the meantime.
+async fn wait_for_schedule(target: &DownloadTarget, state: &StateManager) -> Result<()> {
+    if !state.is_schedule_paused() {
+        return Ok(());
+    }
+    info!("{}: download {}", target, "paused by schedule".yellow());
+    while state.is_schedule_paused() {
+        if state.is_stopped(&target.transfer_hash).await {
+            bail!(DownloadStopped);
+        }
+        tokio::time::sleep(STOP_CHECK_INTERVAL).await;
+    }
+    info!("{}: download {}", target, "resumed".yellow());
+    Ok(())
+}
+
+/// Sends a download request, bounding the wait for response headers.
+async fn send(req: reqwest::RequestBuilder, target: &DownloadTarget) -> Result<reqwest::Response> {
+    // Bound the request itself, not just the connect. put.io can accept the
+    // connection and then stall before sending response headers; without this
+    // timeout `send()` blocks forever, parking the download worker (and, via the
+    // blocked done channel, every orchestration worker) until the whole process
+    // stops pulling. On timeout we error so the retry loop can resume (issue #32).
+    match tokio::time::timeout(REQUEST_TIMEOUT, req.send()).await {
+        Ok(r) => Ok(r?),
+        Err(_) => bail!(
+            "timed out after {:?} waiting for response headers from {}",
+            REQUEST_TIMEOUT,
+            target.from.as_deref().unwrap_or("<no url>")
+        ),
+    }
+}
+
+/// A byte range of a segmented download. `end` is exclusive; `done` counts
+/// the bytes from `start` that are safely on disk.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Segment {
+    start: u64,
+    end: u64,
+    done: u64,
+}
+
+impl Segment {
+    fn remaining(&self) -> u64 {
+        self.end - self.start - self.done
+    }
+}
+
+/// Progress of a segmented download, kept next to the `.downloading` file so
+/// an interrupted download resumes every segment from its own offset.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SegmentState {
+    size: u64,
+    segments: Vec<Segment>,
+}
+
+impl SegmentState {
+    fn new(size: u64, count: u64) -> Self {
+        let len = size.div_ceil(count);
+        let segments = (0..count)
+            .map(|i| Segment {
+                start: i * len,
+                end: ((i + 1) * len).min(size),
+                done: 0,
+            })
+            .filter(|s| s.start < s.end)
+            .collect();
+        Self { size, segments }
+    }
+
+    /// Writes the state to `path` off the runtime. It goes through a
+    /// temporary file named after `writer`, so concurrent saves of different
+    /// segments never leave a torn sidecar behind.
+    async fn save(&self, path: &str, writer: usize) -> Result<()> {
+        let bytes = serde_json::to_vec(self)?;
+        let path = path.to_string();
+        tokio::task::spawn_blocking(move || {
+            let tmp = format!("{}.{}.tmp", path, writer);
+            fs::write(&tmp, bytes)
+                .and_then(|_| fs::rename(&tmp, &path))
+                .with_context(|| format!("cannot write segment state {}", path))
+        })
+        .await?
+    }
+}
+
+fn segments_path(tmp_path: &str) -> String {
+    format!("{}.segments", tmp_path)
+}
+
+/// Downloads `target` as `count` byte ranges fetched concurrently into a
+/// preallocated `tmp_path`. Each segment is retried and resumed on its own.
+async fn fetch_segmented(
+    target: &DownloadTarget,
+    tmp_path: &str,
+    count: u64,
+    client: &reqwest::Client,
+    state: &StateManager,
+) -> Result<()> {
+    let size = u64::try_from(target.size)?;
+    let state_path = segments_path(tmp_path);
+    let resumed = tokio::fs::read(&state_path)
+        .await
+        .ok()
+        .and_then(|b| serde_json::from_slice::<SegmentState>(&b).ok())
+        .filter(|s| s.size == size && Path::new(tmp_path).exists());
+    let progress = match resumed {
+        Some(progress) => {
+            info!("{}: resuming {} segments", target, progress.segments.len());
+            progress
+        }
+        None => {
+            let file = tokio::fs::File::create(tmp_path).await?;
+            file.set_len(size).await?;
+            let progress = SegmentState::new(size, count);
+            info!(
+                "{}: downloading in {} segments",
+                target,
+                progress.segments.len()
+            );
+            progress
+        }
+    };
+    progress.save(&state_path, 0).await?;
+
+    let segments = progress.segments.len();
+    let progress = Mutex::new(progress);
+    futures::future::try_join_all((0..segments).map(|index| {
+        fetch_segment(
+            target,
+            tmp_path,
+            &state_path,
+            index,
+            &progress,
+            client,
+            state,
+        )
+    }))
+    .await?;
+
+    tokio::fs::remove_file(&state_path).await?;
+    Ok(())
+}
+
+async fn fetch_segment(
+    target: &DownloadTarget,
+    tmp_path: &str,
+    state_path: &str,
+    index: usize,
+    progress: &Mutex<SegmentState>,
+    client: &reqwest::Client,
+    state: &StateManager,
+) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        wait_for_schedule(target, state).await?;
+        match fetch_segment_attempt(target, tmp_path, state_path, index, progress, client, state)
+            .await
+        {
+            Ok(()) => return Ok(()),
+            Err(e) if e.is::<DownloadStopped>() => return Err(e),
+            Err(e) if e.is::<SchedulePaused>() => {}
+            Err(e) => {
+                attempt += 1;
+                if attempt >= MAX_ATTEMPTS {
+                    bail!("segment {} failed after {} attempts: {}", index, attempt, e);
+                }
+                warn!(
+                    "{}: segment {} attempt {} failed ({}), resuming",
+                    target, index, attempt, e
+                );
+                tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+            }
+        }
+    }
+}
+
+/// Fetches what's left of segment `index`. Progress is only recorded after
+/// the written bytes were synced to disk, so the sidecar never claims more
+/// than is on disk.
+async fn fetch_segment_attempt(
+    target: &DownloadTarget,
+    tmp_path: &str,
+    state_path: &str,
+    index: usize,
+    progress: &Mutex<SegmentState>,
+    client: &reqwest::Client,
+    state: &StateManager,
+) -> Result<()> {
+    let segment = progress.lock().unwrap().segments[index].clone();
+    let remaining = segment.remaining();
+    if remaining == 0 {
+        return Ok(());
+    }
+    let from = segment.start + segment.done;
+
+    let url = target.from.clone().context("No URL found")?;
+    let req = client.get(url).header(
+        reqwest::header::RANGE,
+        format!("bytes={}-{}", from, segment.end - 1),
+    );
+    let response = send(req, target).await?;
+    let status = response.status();
+    if status != reqwest::StatusCode::PARTIAL_CONTENT {
+        bail!("HTTP {} (expected 206 for a range request)", status);
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(tmp_path)
+        .await?;
+    file.seek(SeekFrom::Start(from)).await?;
+
+    // Records `written` bytes of the segment, once they're on disk.
+    let commit = |written: u64| {
+        let mut progress = progress.lock().unwrap();
+        progress.segments[index].done = segment.done + written;
+        progress.clone()
+    };
+
+    let mut byte_stream = response.bytes_stream();
+    let mut written = 0;
+    let mut last_stop_check = Instant::now();
+    let mut last_save = Instant::now();
+    let result = loop {
+        if last_stop_check.elapsed() >= STOP_CHECK_INTERVAL {
+            if state.is_stopped(&target.transfer_hash).await {
+                break Err(DownloadStopped.into());
+            }
+            if state.is_schedule_paused() {
+                break Err(SchedulePaused.into());
+            }
+            last_stop_check = Instant::now();
+        }
+        if last_save.elapsed() >= SEGMENT_SAVE_INTERVAL {
+            file.flush().await?;
+            file.sync_data().await?;
+            commit(written).save(state_path, index).await?;
+            last_save = Instant::now();
+        }
+        match tokio::time::timeout(STREAM_IDLE_TIMEOUT, byte_stream.next()).await {
+            Ok(Some(Ok(chunk))) => {
+                // Never write past the segment, even if the server sends more.
+                let len = chunk.len().min((remaining - written) as usize);
+                file.write_all(&chunk[..len]).await?;
+                written += len as u64;
+                state.record_downloaded_bytes(len as u64);
+                state
+                    .throttle_download(&target.transfer_hash, len as u64)
+                    .await;
+                if written == remaining {
+                    break Ok(());
+                }
+            }
+            Ok(Some(Err(e))) => break Err(e.into()),
+            Ok(None) => {
+                break Err(anyhow::anyhow!(
+                    "stream ended {} bytes early",
+                    remaining - written
+                ))
+            }
+            Err(_) => {
+                break Err(anyhow::anyhow!(
+                    "stalled: no data received for {:?}",
+                    STREAM_IDLE_TIMEOUT
+                ))
+            }
+        }
+    };
+    // Keep what made it to disk, so a retry or a restart resumes from there.
+    file.flush().await?;
+    file.sync_data().await?;
+    commit(written).save(state_path, index).await?;
+    result
+}
+
 #[derive(Debug, Clone)]
 pub struct DownloadTargetMessage {
     pub download_target: DownloadTarget,
@@ -195,4 +671,7 @@ pub struct DownloadTargetMessage {
 pub enum DownloadDoneStatus {
     Success,
     Failed,
+    /// The transfer was stopped through `torrent-stop` before this target
+    /// finished.
+    Stopped,
 }
@@ -0,0 +1,180 @@
+use super::transfer::{DownloadTarget, MediaType, TargetType};
+use anyhow::{Context, Result};
+use file_owner::PathExt;
+use flate2::read::GzDecoder;
+use log::info;
+use nix::unistd::Uid;
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+/// Archive formats putioarr unpacks, by file name suffix.
+const SUFFIXES: &[&str] = &[".tar.gz", ".tgz", ".tar", ".zip"];
+
+/// Extensions of unpacked files that are handed to the *arrs for import.
+const VIDEO_EXTENSIONS: &[&str] = &[
+    "mkv", "mp4", "m4v", "avi", "mov", "wmv", "mpg", "ts", "webm",
+];
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "flac", "m4a", "m4b", "aac", "ogg", "opus", "wav"];
+
+/// True if `name` is an archive putioarr can unpack.
+pub fn is_supported(name: &str) -> bool {
+    suffix(name).is_some()
+}
+
+fn suffix(name: &str) -> Option<&'static str> {
+    let name = name.to_lowercase();
+    SUFFIXES.iter().copied().find(|s| name.ends_with(s))
+}
+
+/// Unpacks every archive among `targets` next to it and returns the targets
+/// with the unpacked media files added, so the import check waits for them.
+/// An archive that is the whole transfer is unpacked into a folder named
+/// after it, which becomes the transfer's top-level target. Books are only
+/// added with `books` set, as for [`MediaType::from_putio`].
+pub async fn extract(
+    targets: Vec<DownloadTarget>,
+    uid: u32,
+    books: bool,
+) -> Result<Vec<DownloadTarget>> {
+    tokio::task::spawn_blocking(move || {
+        let mut out = Vec::with_capacity(targets.len());
+        for target in targets {
+            if !target.archive || target.extracted {
+                out.push(target);
+                continue;
+            }
+            let archive = Path::new(&target.to);
+            let name = archive
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let parent = archive.parent().context("archive without a directory")?;
+            let dest = if target.top_level {
+                let stem = &name[..name.len() - suffix(&name).map_or(0, str::len)];
+                parent.join(stem)
+            } else {
+                parent.to_path_buf()
+            };
+            fs::create_dir_all(&dest)?;
+            chown(&dest, uid)?;
+
+            info!("{}: extracting", target);
+            let files = unpack(archive, &name, &dest)
+                .with_context(|| format!("cannot extract {}", target.to))?;
+            for file in &files {
+                chown(file, uid)?;
+            }
+            info!("{}: extracted {} files", target, files.len());
+
+            if target.top_level {
+                out.push(DownloadTarget {
+                    from: None,
+                    to: dest.to_string_lossy().to_string(),
+                    target_type: TargetType::Directory,
+                    top_level: true,
+                    transfer_hash: target.transfer_hash.clone(),
+                    media_type: None,
+                    size: 0,
+                    crc32: None,
+                    archive: false,
+                    extracted: false,
+                });
+            }
+            for file in files {
+                let Some(media_type) = media_type(&file, books) else {
+                    continue;
+                };
+                out.push(DownloadTarget {
+                    from: None,
+                    size: fs::metadata(&file).map_or(0, |m| m.len() as i64),
+                    to: file.to_string_lossy().to_string(),
+                    target_type: TargetType::File,
+                    top_level: false,
+                    transfer_hash: target.transfer_hash.clone(),
+                    media_type: Some(media_type),
+                    crc32: None,
+                    archive: false,
+                    extracted: false,
+                });
+            }
+            out.push(DownloadTarget {
+                top_level: false,
+                extracted: true,
+                ..target
+            });
+        }
+        Ok(out)
+    })
+    .await?
+}
+
+/// Unpacks `archive` into `dest`, returning the files written. Entries that
+/// would end up outside `dest` are skipped.
+fn unpack(archive: &Path, name: &str, dest: &Path) -> Result<Vec<PathBuf>> {
+    let file = File::open(archive)?;
+    match suffix(name) {
+        Some(".zip") => unzip(file, dest),
+        Some(".tar") => untar(file, dest),
+        _ => untar(GzDecoder::new(file), dest),
+    }
+}
+
+fn unzip(file: File, dest: &Path) -> Result<Vec<PathBuf>> {
+    let mut zip = zip::ZipArchive::new(file)?;
+    let mut files = vec![];
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        let Some(relative) = entry.enclosed_name() else {
+            continue;
+        };
+        let path = dest.join(relative);
+        if entry.is_dir() {
+            fs::create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        io::copy(&mut entry, &mut File::create(&path)?)?;
+        files.push(path);
+    }
+    Ok(files)
+}
+
+fn untar(reader: impl Read, dest: &Path) -> Result<Vec<PathBuf>> {
+    let mut tar = tar::Archive::new(reader);
+    let mut files = vec![];
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let relative = entry.path()?.into_owned();
+        let is_file = entry.header().entry_type().is_file();
+        if entry.unpack_in(dest)? && is_file {
+            files.push(dest.join(relative));
+        }
+    }
+    Ok(files)
+}
+
+fn media_type(path: &Path, books: bool) -> Option<MediaType> {
+    if books && MediaType::is_book(&path.to_string_lossy()) {
+        return Some(MediaType::Book);
+    }
+    let extension = path.extension()?.to_string_lossy().to_lowercase();
+    if VIDEO_EXTENSIONS.contains(&extension.as_str()) {
+        Some(MediaType::Video)
+    } else if AUDIO_EXTENSIONS.contains(&extension.as_str()) {
+        Some(MediaType::Audio)
+    } else {
+        None
+    }
+}
+
+fn chown(path: &Path, uid: u32) -> Result<()> {
+    if Uid::effective().is_root() {
+        path.set_owner(uid)?;
+    }
+    Ok(())
+}
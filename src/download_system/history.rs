@@ -0,0 +1,568 @@
+use super::transfer::DownloadTarget;
+use crate::{
+    services::arr::{ArrApp, ArrHistoryRecord, ArrHistoryResponse},
+    AppData, Config,
+};
+use actix_web::web::Data;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use colored::*;
+use log::{error, info};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{sync::Notify, time::sleep};
+
+/// How often an *arr's history is read once imports are reported through
+/// the webhook, as a safety net for missed webhooks.
+const WEBHOOK_POLL_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Records per request when reading a history for the first time.
+const INITIAL_PAGE_SIZE: u32 = 1000;
+
+/// Records per request when catching up with new ones.
+const PAGE_SIZE: u32 = 100;
+
+/// What the *arrs' history says about imports, kept up to date by [`watch`]
+/// so import checks don't each have to page through every *arr's history.
+pub struct ArrHistory {
+    apps: Vec<(ArrApp, Mutex<Index>)>,
+    lookback: Lookback,
+    /// Set once every *arr's history was read (or failed to be) once.
+    loaded: AtomicBool,
+    loaded_notify: Notify,
+}
+
+#[derive(Default)]
+struct Index {
+    /// Id of the newest record seen. Only newer records are fetched.
+    last_id: Option<i64>,
+    /// Set once the history has been read completely.
+    ready: bool,
+    /// `droppedPath` of every import.
+    dropped_paths: HashSet<String>,
+    /// Downloads by uppercase hash.
+    downloads: HashMap<String, Download>,
+}
+
+#[derive(Default)]
+struct Download {
+    /// Names of the files imported from the download.
+    files: HashSet<String>,
+    /// Ignored in the *arr's queue (it won't import anything), or Lidarr's
+    /// event for a completely imported download.
+    done: bool,
+}
+
+impl Index {
+    fn add(&mut self, record: ArrHistoryRecord, import_event: &str) {
+        let dropped_path = record.data.get("droppedPath").cloned().flatten();
+        let download = record
+            .download_id
+            .map(|id| self.downloads.entry(id.to_uppercase()).or_default());
+        match record.event_type.as_str() {
+            "downloadIgnored" | "downloadImported" => {
+                if let Some(download) = download {
+                    download.done = true;
+                }
+            }
+            event if event == import_event => {
+                let Some(path) = dropped_path else { return };
+                if let Some(download) = download {
+                    // Matched by name, since the *arr may see the download
+                    // through a different mount than putioarr.
+                    if let Some(name) = Path::new(&path).file_name() {
+                        download.files.insert(name.to_string_lossy().to_string());
+                    }
+                }
+                self.dropped_paths.insert(path);
+            }
+            _ => {}
+        }
+    }
+
+    fn is_imported(&self, hash: Option<&str>, known: bool, target: &str) -> bool {
+        if !known {
+            return self.dropped_paths.contains(target);
+        }
+        let Some(download) = hash.and_then(|h| self.downloads.get(&h.to_uppercase())) else {
+            return false;
+        };
+        download.done
+            || Path::new(target)
+                .file_name()
+                .is_some_and(|n| download.files.contains(n.to_string_lossy().as_ref()))
+    }
+}
+
+impl ArrHistory {
+    pub fn new(config: &Config, client: reqwest::Client) -> Self {
+        let apps = config
+            .all_arrs()
+            .into_iter()
+            .map(|(name, kind, c)| {
+                (
+                    ArrApp::new(name, kind, c, client.clone()),
+                    Mutex::new(Index::default()),
+                )
+            })
+            .collect();
+        Self {
+            apps,
+            lookback: Lookback::new(config),
+            loaded: AtomicBool::new(false),
+            loaded_notify: Notify::new(),
+        }
+    }
+
+    /// Waits until [`watch`] read every *arr's history once.
+    pub async fn wait_loaded(&self) {
+        loop {
+            let notified = self.loaded_notify.notified();
+            if self.loaded.load(Ordering::SeqCst) {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// True if any *arr has history for the download with this hash. Imports
+    /// of downloads no *arr knows by hash (e.g. orphans, or added by hand) are
+    /// matched on their exact path instead.
+    pub fn knows(&self, hash: &str) -> bool {
+        let hash = hash.to_uppercase();
+        self.apps
+            .iter()
+            .any(|(_, index)| index.lock().unwrap().downloads.contains_key(&hash))
+    }
+
+    /// True if an *arr that handles `target`'s media type imported it, or if
+    /// none does.
+    pub fn is_imported(&self, hash: Option<&str>, known: bool, target: &DownloadTarget) -> bool {
+        let mut eligible = false;
+        for (app, index) in &self.apps {
+            // Only ask an *arr about files matching its media type.
+            if target.media_type.is_some_and(|mt| !app.kind.handles(mt)) {
+                continue;
+            }
+            eligible = true;
+            if index.lock().unwrap().is_imported(hash, known, &target.to) {
+                info!(
+                    "{}: found imported by {}",
+                    target,
+                    app.to_string().bright_blue()
+                );
+                return true;
+            }
+        }
+        // If no service was eligible for this target, treat it as imported
+        // (otherwise an audio file with no Lidarr would block forever).
+        !eligible
+    }
+}
+
+/// Reads every *arr's history, then fetches new records every
+/// `polling_interval`. Once an *arr reports imports through the webhook, its
+/// history is only read every [`WEBHOOK_POLL_INTERVAL`], unless a reported
+/// import still needs confirming.
+pub async fn watch(app_data: Data<AppData>) {
+    let history = &app_data.history;
+    let interval = Duration::from_secs(app_data.config.polling_interval);
+    for (app, index) in &history.apps {
+        refresh_logged(&app_data, app, index).await;
+    }
+    history.loaded.store(true, Ordering::SeqCst);
+    history.loaded_notify.notify_waiters();
+
+    let state = &app_data.state;
+    let mut refreshed = vec![Instant::now(); history.apps.len()];
+    loop {
+        sleep(interval).await;
+        let confirming = state.has_webhook_imports().await;
+        for ((app, index), refreshed) in history.apps.iter().zip(&mut refreshed) {
+            let webhooks = match &app.category {
+                Some(category) => state.webhooks_received(category).await,
+                None => false,
+            };
+            if webhooks && !confirming && refreshed.elapsed() < WEBHOOK_POLL_INTERVAL {
+                continue;
+            }
+            refresh_logged(&app_data, app, index).await;
+            *refreshed = Instant::now();
+        }
+    }
+}
+
+/// Adds the records `app` added since the last call to the index, logging
+/// any error. The first call reads the whole history.
+async fn refresh_logged(app_data: &Data<AppData>, app: &ArrApp, index: &Mutex<Index>) {
+    if let Err(e) = refresh(app, index, &app_data.history.lookback).await {
+        // A misconfigured/unreachable *arr fails on every poll; throttle
+        // the log so it doesn't fill the disk over time (issue #21).
+        if app_data.state.should_log_arr_error(&app.name).await {
+            error!(
+                "Error retrieving history from {} (suppressing repeats for {:?}): {}",
+                app,
+                crate::state::StateManager::ARR_ERROR_LOG_INTERVAL,
+                e
+            );
+        }
+    }
+}
+
+/// Adds the records newer than the last one seen to the index.
+async fn refresh(app: &ArrApp, index: &Mutex<Index>, lookback: &Lookback) -> Result<()> {
+    let (last_id, ready) = {
+        let index = index.lock().unwrap();
+        (index.last_id, index.ready)
+    };
+    // Past the first read, everything new is wanted.
+    let mut pager = if ready {
+        Pager::new(PAGE_SIZE, last_id, &Lookback::default())
+    } else {
+        Pager::new(INITIAL_PAGE_SIZE, last_id, lookback)
+    };
+    while let Some(page) = pager.next_page() {
+        pager.add(app.history_page(page, pager.page_size).await?);
+    }
+
+    let mut index = index.lock().unwrap();
+    let import_event = app.kind.import_event();
+    for record in pager.records {
+        index.last_id = Some(index.last_id.map_or(record.id, |id| id.max(record.id)));
+        index.add(record, import_event);
+    }
+    index.ready = true;
+    Ok(())
+}
+
+/// How much of a history is read at startup.
+#[derive(Debug, Default, Clone)]
+struct Lookback {
+    /// At most this many records.
+    max_records: Option<usize>,
+    /// No records older than this.
+    since: Option<DateTime<Utc>>,
+}
+
+impl Lookback {
+    fn new(config: &Config) -> Self {
+        Self {
+            max_records: (config.history_lookback_records > 0)
+                .then_some(config.history_lookback_records),
+            since: (config.history_lookback_days > 0)
+                .then(|| Utc::now() - chrono::Duration::days(config.history_lookback_days as i64)),
+        }
+    }
+}
+
+/// Walks a history newest record first, page by page, until it reaches the
+/// records seen before, the lookback limit or the end of the history.
+struct Pager {
+    page_size: u32,
+    last_id: Option<i64>,
+    lookback: Lookback,
+    /// Last page fetched (pages are 1-based), 0 before the first.
+    page: u32,
+    /// Records fetched so far, including ones that weren't wanted.
+    fetched: usize,
+    records: Vec<ArrHistoryRecord>,
+    done: bool,
+}
+
+impl Pager {
+    fn new(page_size: u32, last_id: Option<i64>, lookback: &Lookback) -> Self {
+        Self {
+            page_size,
+            last_id,
+            lookback: lookback.clone(),
+            page: 0,
+            fetched: 0,
+            records: vec![],
+            done: false,
+        }
+    }
+
+    /// The page to fetch next, or None once done.
+    fn next_page(&self) -> Option<u32> {
+        (!self.done).then_some(self.page + 1)
+    }
+
+    /// Takes in the page returned for [`Self::next_page`].
+    fn add(&mut self, response: ArrHistoryResponse) {
+        self.page += 1;
+        if response.records.is_empty() {
+            self.done = true;
+            return;
+        }
+        self.fetched += response.records.len();
+        for record in response.records {
+            let seen = self.last_id.is_some_and(|id| record.id <= id);
+            let full = self
+                .lookback
+                .max_records
+                .is_some_and(|max| self.records.len() >= max);
+            let too_old = self.lookback.since.is_some_and(|since| {
+                record
+                    .date
+                    .as_deref()
+                    .and_then(|d| DateTime::parse_from_rfc3339(d).ok())
+                    .is_some_and(|d| d < since)
+            });
+            if seen || full || too_old {
+                self.done = true;
+                return;
+            }
+            self.records.push(record);
+        }
+        let full = self
+            .lookback
+            .max_records
+            .is_some_and(|max| self.records.len() >= max);
+        if full || self.fetched >= response.total_records as usize {
+            self.done = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::arr::ArrKind;
+
+    fn response(json: &str) -> ArrHistoryResponse {
+        serde_json::from_str(json).unwrap()
+    }
+
+    /// Pages through `pages` like [`refresh`] does, returning the ids of the
+    /// records kept and the pages requested.
+    fn walk(mut pager: Pager, pages: &[&str]) -> (Vec<i64>, Vec<u32>) {
+        let mut requested = vec![];
+        while let Some(page) = pager.next_page() {
+            requested.push(page);
+            let json = pages.get(page as usize - 1).copied().unwrap_or(EMPTY);
+            pager.add(response(json));
+        }
+        (pager.records.iter().map(|r| r.id).collect(), requested)
+    }
+
+    fn index(kind: ArrKind, json: &str) -> Index {
+        let mut index = Index::default();
+        for record in response(json).records {
+            index.add(record, kind.import_event());
+        }
+        index
+    }
+
+    const EMPTY: &str = r#"{"page": 9, "pageSize": 2, "totalRecords": 0, "records": []}"#;
+
+    const PAGE_1: &str = r#"{
+        "page": 1, "pageSize": 2, "sortKey": "date", "sortDirection": "descending",
+        "totalRecords": 3,
+        "records": [
+            {"id": 30, "date": "2024-05-03T10:00:00Z", "eventType": "grabbed",
+             "downloadId": "AAAA", "data": {}},
+            {"id": 20, "date": "2024-05-02T10:00:00Z", "eventType": "grabbed",
+             "downloadId": "BBBB", "data": {}}
+        ]
+    }"#;
+
+    const PAGE_2: &str = r#"{
+        "page": 2, "pageSize": 2, "sortKey": "date", "sortDirection": "descending",
+        "totalRecords": 3,
+        "records": [
+            {"id": 10, "date": "2024-05-01T10:00:00Z", "eventType": "grabbed",
+             "downloadId": "CCCC", "data": {}}
+        ]
+    }"#;
+
+    #[test]
+    fn pages_through_the_whole_history() {
+        let pager = Pager::new(2, None, &Lookback::default());
+        assert_eq!(
+            walk(pager, &[PAGE_1, PAGE_2]),
+            (vec![30, 20, 10], vec![1, 2])
+        );
+    }
+
+    #[test]
+    fn stops_at_records_seen_before() {
+        let pager = Pager::new(2, Some(20), &Lookback::default());
+        assert_eq!(walk(pager, &[PAGE_1, PAGE_2]), (vec![30], vec![1]));
+    }
+
+    #[test]
+    fn stops_at_an_empty_page() {
+        // totalRecords claims more than there is.
+        let lying = PAGE_1.replace(r#""totalRecords": 3"#, r#""totalRecords": 50"#);
+        let pager = Pager::new(2, None, &Lookback::default());
+        assert_eq!(walk(pager, &[&lying]), (vec![30, 20], vec![1, 2]));
+    }
+
+    #[test]
+    fn stops_at_max_records() {
+        let lookback = Lookback {
+            max_records: Some(2),
+            since: None,
+        };
+        let pager = Pager::new(2, None, &lookback);
+        assert_eq!(walk(pager, &[PAGE_1, PAGE_2]), (vec![30, 20], vec![1]));
+    }
+
+    #[test]
+    fn stops_at_max_age() {
+        let lookback = Lookback {
+            max_records: None,
+            since: Some("2024-05-02T12:00:00Z".parse().unwrap()),
+        };
+        let pager = Pager::new(2, None, &lookback);
+        assert_eq!(walk(pager, &[PAGE_1, PAGE_2]), (vec![30], vec![1]));
+    }
+
+    const SONARR: &str = r#"{
+        "page": 1, "pageSize": 1000, "totalRecords": 3,
+        "records": [
+            {"id": 3, "episodeId": 12, "seriesId": 1, "sourceTitle": "Show.S01E02.1080p",
+             "date": "2024-05-03T10:00:00Z", "downloadId": "AAAA",
+             "eventType": "downloadFolderImported",
+             "data": {"droppedPath": "/mnt/downloads/tv/Show.S01/Show.S01E02.mkv",
+                      "importedPath": "/tv/Show/Season 1/Show - S01E02.mkv"}},
+            {"id": 2, "episodeId": 11, "seriesId": 1, "sourceTitle": "Show.S01E01.1080p",
+             "date": "2024-05-02T10:00:00Z", "downloadId": null,
+             "eventType": "downloadFolderImported",
+             "data": {"droppedPath": "/downloads/tv/Show.S01E01.mkv", "importedPath": null}},
+            {"id": 1, "episodeId": 12, "seriesId": 1, "sourceTitle": "Show.S01",
+             "date": "2024-05-01T10:00:00Z", "downloadId": "AAAA", "eventType": "grabbed",
+             "data": {"indexer": "Example"}}
+        ]
+    }"#;
+
+    #[test]
+    fn sonarr_imports() {
+        let index = index(ArrKind::Sonarr, SONARR);
+        // Matched by name within the download, despite the different mount.
+        let hash = Some("aaaa");
+        assert!(index.is_imported(hash, true, "/downloads/tv/Show.S01/Show.S01E02.mkv"));
+        assert!(!index.is_imported(hash, true, "/downloads/tv/Show.S01/Show.S01E03.mkv"));
+        // Without a download id only the exact path matches.
+        assert!(index.is_imported(None, false, "/downloads/tv/Show.S01E01.mkv"));
+        assert!(!index.is_imported(None, false, "/data/tv/Show.S01E01.mkv"));
+    }
+
+    const RADARR: &str = r#"{
+        "page": 1, "pageSize": 1000, "totalRecords": 2,
+        "records": [
+            {"id": 8, "movieId": 4, "sourceTitle": "Movie.2023.2160p",
+             "date": "2024-05-02T10:00:00Z", "downloadId": "BBBB",
+             "eventType": "downloadFolderImported",
+             "data": {"droppedPath": "/downloads/movies/Movie.2023/Movie.2023.mkv"}},
+            {"id": 7, "movieId": 5, "sourceTitle": "Other.2022",
+             "date": "2024-05-01T10:00:00Z", "downloadId": "CCCC",
+             "eventType": "downloadIgnored", "data": {"message": "Manually ignored"}}
+        ]
+    }"#;
+
+    #[test]
+    fn radarr_imports() {
+        let index = index(ArrKind::Radarr, RADARR);
+        assert!(index.is_imported(
+            Some("bbbb"),
+            true,
+            "/downloads/movies/Movie.2023/Movie.2023.mkv"
+        ));
+        // An ignored download counts as done for every file.
+        assert!(index.is_imported(Some("cccc"), true, "/downloads/movies/Other.2022.mkv"));
+        assert!(!index.is_imported(
+            Some("dddd"),
+            true,
+            "/downloads/movies/Movie.2023/Movie.2023.mkv"
+        ));
+    }
+
+    const WHISPARR: &str = r#"{
+        "page": 1, "pageSize": 1000, "totalRecords": 1,
+        "records": [
+            {"id": 5, "date": "2024-05-01T10:00:00Z", "downloadId": "EEEE",
+             "eventType": "downloadFolderImported",
+             "data": {"droppedPath": "/downloads/whisparr/Scene.mp4"}}
+        ]
+    }"#;
+
+    #[test]
+    fn whisparr_imports() {
+        let index = index(ArrKind::Whisparr, WHISPARR);
+        assert!(index.is_imported(Some("eeee"), true, "/downloads/whisparr/Scene.mp4"));
+        assert!(index.is_imported(None, false, "/downloads/whisparr/Scene.mp4"));
+    }
+
+    const LIDARR: &str = r#"{
+        "page": 1, "pageSize": 1000, "totalRecords": 3,
+        "records": [
+            {"id": 42, "albumId": 3, "artistId": 2, "sourceTitle": "Artist - Album",
+             "date": "2024-05-02T10:00:00Z", "downloadId": "FFFF",
+             "eventType": "trackFileImported",
+             "data": {"droppedPath": "/downloads/music/Artist - Album/01 Track.flac"}},
+            {"id": 41, "albumId": 3, "artistId": 2, "sourceTitle": "Artist - Album",
+             "date": "2024-05-02T10:00:00Z", "downloadId": "1111",
+             "eventType": "downloadImported", "data": {}},
+            {"id": 40, "albumId": 3, "artistId": 2, "sourceTitle": "Artist - Album",
+             "date": "2024-05-01T10:00:00Z", "downloadId": "2222",
+             "eventType": "downloadFolderImported",
+             "data": {"droppedPath": "/downloads/music/Other/01 Track.flac"}}
+        ]
+    }"#;
+
+    #[test]
+    fn lidarr_imports() {
+        let index = index(ArrKind::Lidarr, LIDARR);
+        assert!(index.is_imported(
+            Some("ffff"),
+            true,
+            "/downloads/music/Artist - Album/01 Track.flac"
+        ));
+        assert!(!index.is_imported(
+            Some("ffff"),
+            true,
+            "/downloads/music/Artist - Album/02 Track.flac"
+        ));
+        // A completely imported download.
+        assert!(index.is_imported(Some("1111"), true, "/downloads/music/Any/02 Track.flac"));
+        // Not Lidarr's import event.
+        assert!(!index.is_imported(Some("2222"), true, "/downloads/music/Other/01 Track.flac"));
+    }
+
+    const READARR: &str = r#"{
+        "page": 1, "pageSize": 1000, "totalRecords": 2,
+        "records": [
+            {"id": 9, "bookId": 6, "authorId": 3, "sourceTitle": "Author - Book (2020) [EPUB]",
+             "date": "2024-05-02T10:00:00Z", "downloadId": "BOOC",
+             "eventType": "bookFileImported",
+             "data": {"droppedPath": "/downloads/books/Author - Book/Book.epub"}},
+            {"id": 8, "bookId": 7, "authorId": 3, "sourceTitle": "Author - Audiobook",
+             "date": "2024-05-01T10:00:00Z", "downloadId": "AUD1",
+             "eventType": "downloadImported", "data": {}}
+        ]
+    }"#;
+
+    #[test]
+    fn readarr_imports() {
+        let index = index(ArrKind::Readarr, READARR);
+        assert!(index.is_imported(
+            Some("booc"),
+            true,
+            "/downloads/books/Author - Book/Book.epub"
+        ));
+        assert!(!index.is_imported(
+            Some("booc"),
+            true,
+            "/downloads/books/Author - Book/Book.pdf"
+        ));
+        assert!(index.is_imported(Some("aud1"), true, "/downloads/books/Audiobook/01.mp3"));
+    }
+}
@@ -0,0 +1,100 @@
+use super::transfer::Transfer;
+use crate::AppData;
+use actix_web::web::Data;
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use std::{fmt, process::Stdio, time::Duration};
+use tokio::{process::Command, time::timeout};
+
+/// When a hook command runs, passed to it as `PUTIOARR_EVENT`.
+#[derive(Debug, Clone, Copy)]
+pub enum Event {
+    /// All of the transfer's files are on local disk.
+    Downloaded,
+    /// The *arr imported the transfer; its local files are about to be removed.
+    Imported,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Downloaded => write!(f, "downloaded"),
+            Event::Imported => write!(f, "imported"),
+        }
+    }
+}
+
+/// Runs the hook command configured for the transfer's category (or the
+/// global one) through `sh -c`, logging its output. Fails if it exits
+/// non-zero or outlives `hook_timeout_secs`. Does nothing without a command.
+pub async fn run(app_data: &Data<AppData>, transfer: &Transfer, event: Event) -> Result<()> {
+    let config = &app_data.config;
+    let category = match &transfer.hash {
+        Some(hash) => app_data
+            .state
+            .get_transfer(hash)
+            .await
+            .map(|s| s.source_category),
+        None => None,
+    };
+    let command = config
+        .all_arrs()
+        .into_iter()
+        .find(|(_, _, arr)| category.is_some() && arr.category == category)
+        .and_then(|(_, _, arr)| arr.hook_command.clone())
+        .or_else(|| config.hook_command.clone());
+    let Some(command) = command else {
+        return Ok(());
+    };
+
+    let targets = transfer.targets.as_deref().unwrap_or_default();
+    let path = targets
+        .iter()
+        .find(|t| t.top_level)
+        .map(|t| t.to.clone())
+        .unwrap_or_default();
+    let size: i64 = targets.iter().map(|t| t.size).sum();
+
+    info!("{}: running {} hook", transfer, event);
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .env("PUTIOARR_EVENT", event.to_string())
+        .env("PUTIOARR_NAME", &transfer.name)
+        .env(
+            "PUTIOARR_HASH",
+            transfer.hash.as_deref().unwrap_or_default(),
+        )
+        .env("PUTIOARR_CATEGORY", category.unwrap_or_default())
+        .env("PUTIOARR_PATH", path)
+        .env(
+            "PUTIOARR_FILE_ID",
+            transfer
+                .file_id
+                .map(|id| id.to_string())
+                .unwrap_or_default(),
+        )
+        .env("PUTIOARR_SIZE", size.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("cannot run hook {:?}", command))?;
+
+    let limit = Duration::from_secs(config.hook_timeout_secs);
+    let output = match timeout(limit, child.wait_with_output()).await {
+        Ok(output) => output?,
+        Err(_) => bail!("hook {:?} timed out after {:?}", command, limit),
+    };
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        info!("{}: hook: {}", transfer, line);
+    }
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        warn!("{}: hook: {}", transfer, line);
+    }
+    if !output.status.success() {
+        bail!("hook {:?} failed: {}", command, output.status);
+    }
+    Ok(())
+}
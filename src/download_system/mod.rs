@@ -2,29 +2,53 @@ use crate::AppData;
 use actix_web::web::Data;
 use anyhow::Result;
 
+pub mod blackhole;
 pub mod download;
+pub mod extract;
+pub mod history;
+pub mod hook;
 pub mod orchestration;
+pub mod priority;
+pub mod queue;
+pub mod ratelimit;
+pub mod schedule;
+pub mod staging;
 pub mod transfer;
 
 pub async fn start(app_data: Data<AppData>) -> Result<()> {
+    // Apply the schedule before any worker starts, so the first downloads
+    // already honour it.
+    if !app_data.config.schedule.is_empty() {
+        schedule::apply(&app_data);
+        let data = app_data.clone();
+        actix_rt::spawn(async { schedule::watch(data).await });
+    }
+
+    // Transfers aren't looked at before the history was read once, so imports
+    // that happened while putioarr was down are known.
+    let data = app_data.clone();
+    actix_rt::spawn(async { history::watch(data).await });
+
     let (sender, receiver) = async_channel::unbounded();
-    let (download_sender, download_receiver) = async_channel::unbounded();
     let data = app_data.clone();
     let tx = sender.clone();
     actix_rt::spawn(async { transfer::produce_transfers(data, tx).await });
 
+    if !app_data.config.blackhole_directories.is_empty() {
+        let data = app_data.clone();
+        actix_rt::spawn(async { blackhole::watch(data).await });
+    }
+
     for id in 0..app_data.config.orchestration_workers {
         let data = app_data.clone();
         let tx = sender.clone();
         let rx = receiver.clone();
-        let dtx = download_sender.clone();
-        orchestration::Worker::start(id, data, tx, rx, dtx);
+        orchestration::Worker::start(id, data, tx, rx);
     }
 
     for id in 0..app_data.config.download_workers {
-        let drx = download_receiver.clone();
         let data = app_data.clone();
-        download::Worker::start(id, data, drx)
+        download::Worker::start(id, data)
     }
 
     Ok(())
@@ -1,23 +1,30 @@
 use crate::{
     download_system::{
         download::{DownloadDoneStatus, DownloadTargetMessage},
-        transfer::Transfer,
+        extract,
+        hook::{self, Event},
+        staging,
+        transfer::{DownloadTarget, TargetType, Transfer},
     },
     services::putio,
-    AppData,
+    state::SpaceReservation,
+    utils, AppData,
 };
 use actix_web::web::Data;
 use anyhow::Result;
 use async_channel::{Receiver, Sender};
 use colored::*;
-use log::{info, warn};
+use log::{error, info, warn};
 use std::{
+    collections::HashSet,
     fs,
+    os::unix::fs::MetadataExt,
+    path::Path,
     time::{Duration, Instant},
 };
 use tokio::{fs::metadata, time::sleep};
 
-use super::transfer::TransferMessage;
+use super::{queue::Stage, transfer::TransferMessage};
 
 #[derive(Clone)]
 pub struct Worker {
@@ -25,7 +32,6 @@ pub struct Worker {
     app_data: Data<AppData>,
     tx: Sender<TransferMessage>,
     rx: Receiver<TransferMessage>,
-    dtx: Sender<DownloadTargetMessage>,
 }
 
 impl Worker {
@@ -34,14 +40,12 @@ impl Worker {
         app_data: Data<AppData>,
         tx: Sender<TransferMessage>,
         rx: Receiver<TransferMessage>,
-        dtx: Sender<DownloadTargetMessage>,
     ) {
         let s = Self {
             _id: id,
             app_data,
             tx,
             rx,
-            dtx,
         };
         let _join_handle = actix_rt::spawn(async move { s.work().await });
     }
@@ -62,8 +66,18 @@ impl Worker {
                     }
                 }
                 TransferMessage::Downloaded(t) => {
-                    let tx = self.tx.clone();
-                    actix_rt::spawn(async { watch_for_import(app_data, tx, t).await });
+                    // A transfer downloaded again (after a restart or a failed
+                    // verify) may already be watched; one watcher is enough.
+                    if app_data.state.begin_watching(t.transfer_id).await {
+                        let tx = self.tx.clone();
+                        actix_rt::spawn(async move {
+                            let id = t.transfer_id;
+                            let state = app_data.state.clone();
+                            let result = watch_for_import(app_data, tx, t).await;
+                            state.end_watching(id).await;
+                            result
+                        });
+                    }
                 }
                 TransferMessage::Imported(t) => {
                     actix_rt::spawn(async { watch_seeding(app_data, t).await });
@@ -76,6 +90,24 @@ impl Worker {
     /// complete and forwards it for import. Returns Err on any failure so the
     /// caller can log it without ending the worker (see issue #34).
     async fn handle_queued(&self, t: Transfer) -> Result<()> {
+        let id = t.transfer_id;
+        self.app_data.state.begin_processing(id).await;
+        let result = self.download_transfer(t).await;
+        if let Ok(true) = result {
+            return Ok(());
+        }
+        self.app_data.state.end_processing(id).await;
+        self.app_data.state.release_space(id);
+        if result.is_err() {
+            self.app_data.state.set_waiting_for_space(id, None).await;
+            self.app_data.queue.remove(id);
+        }
+        result.map(|_| ())
+    }
+
+    /// Returns true if the transfer was handed to [`wait_for_space`], which
+    /// keeps it marked as processing until it's queued again.
+    async fn download_transfer(&self, t: Transfer) -> Result<bool> {
         info!("{}: download {}", t, "started".yellow());
         // Reuse targets computed when the transfer was discovered if present —
         // watch-folder orphans precompute them with the correct base dir
@@ -90,21 +122,63 @@ impl Worker {
         // below pass vacuously and mark the transfer complete.
         if targets.is_empty() {
             warn!("{}: no downloadable targets, skipping", t);
-            return Ok(());
+            self.app_data.queue.remove(t.transfer_id);
+            return Ok(false);
+        }
+        let t = Transfer {
+            targets: Some(targets.clone()),
+            ..t
+        };
+        self.app_data.queue.set(&t, Stage::Downloading);
+        // With an incomplete directory the targets are downloaded there and
+        // the transfer is moved into place once all of them succeeded.
+        let staged = match &self.app_data.config.incomplete_directory {
+            Some(dir) => {
+                fs::create_dir_all(dir)?;
+                staging::stage(&targets, dir)
+            }
+            None => None,
+        };
+        let download_targets = staged.as_deref().unwrap_or(&targets);
+        if let Some(reason) = reserve_space(&self.app_data, &t, download_targets).await? {
+            let state = &self.app_data.state;
+            if state.waiting_for_space(t.transfer_id).await.is_none() {
+                warn!("{}: {}", t, reason);
+            }
+            state
+                .set_waiting_for_space(t.transfer_id, Some(reason))
+                .await;
+            // Waiting here would hold up the worker, and with enough
+            // transfers waiting no worker would be left to start the import
+            // watchers that free the space.
+            let app_data = self.app_data.clone();
+            let tx = self.tx.clone();
+            let download_targets = download_targets.to_vec();
+            actix_rt::spawn(async move { wait_for_space(app_data, tx, t, download_targets).await });
+            return Ok(true);
         }
         // A status channel per target for the download workers to report back.
         let done_channels: Vec<(Sender<DownloadDoneStatus>, Receiver<DownloadDoneStatus>)> =
-            targets.iter().map(|_| async_channel::unbounded()).collect();
+            download_targets
+                .iter()
+                .map(|_| async_channel::unbounded())
+                .collect();
 
-        for (i, target) in targets.iter().enumerate() {
-            let done_tx = done_channels[i].0.clone();
-            self.dtx
-                .send(DownloadTargetMessage {
-                    download_target: target.clone(),
-                    tx: done_tx,
-                })
-                .await?;
-        }
+        let msgs = download_targets
+            .iter()
+            .zip(&done_channels)
+            .map(|(target, (done_tx, _))| DownloadTargetMessage {
+                download_target: target.clone(),
+                tx: done_tx.clone(),
+            })
+            .collect();
+        let size = targets.iter().map(|t| t.size).sum();
+        self.app_data.download_queue.push(
+            &targets[0].transfer_hash,
+            size,
+            self.category_priority(&t).await,
+            msgs,
+        );
 
         // Wait for all the workers having sent back their status.
         let mut all_downloaded = vec![];
@@ -117,26 +191,222 @@ impl Worker {
             .all(|d| matches!(d, DownloadDoneStatus::Success))
         {
             info!("{}: download {}", t, "done".blue());
+            if let Some(staged) = &staged {
+                let from = staged.iter().find(|s| s.top_level);
+                let to = targets.iter().find(|s| s.top_level);
+                if let (Some(from), Some(to)) = (from, to) {
+                    staging::move_into_place(
+                        from.to.clone(),
+                        to.to.clone(),
+                        self.app_data.config.uid,
+                    )
+                    .await?;
+                    info!("{}: moved to {}", t, to.to);
+                }
+            }
+            // Unpacked files become targets of their own, so the import check
+            // waits for them.
+            let t = if targets.iter().any(|t| t.archive) {
+                let books = t.wants_books().await;
+                let uid = self.app_data.config.uid;
+                Transfer {
+                    targets: Some(extract::extract(targets, uid, books).await?),
+                    ..t
+                }
+            } else {
+                t
+            };
+            if let Err(e) = hook::run(&self.app_data, &t, Event::Downloaded).await {
+                warn!("{}: {:#}", t, e);
+                if self.app_data.config.hook_blocks_completion {
+                    warn!("{}: not reporting it as downloaded", t);
+                    self.forget_failed(&t).await;
+                    return Ok(false);
+                }
+            }
             // The files now exist locally, so it's safe to report this transfer
             // as complete to the *arr (see issue #16).
             self.app_data.state.mark_local_complete(t.transfer_id).await;
-            self.tx
-                .send(TransferMessage::Downloaded(Transfer {
-                    targets: Some(targets),
-                    ..t
-                }))
-                .await?;
+            self.app_data.queue.set(&t, Stage::Downloaded);
+            self.tx.send(TransferMessage::Downloaded(t)).await?;
+        } else if all_downloaded
+            .iter()
+            .any(|d| matches!(d, DownloadDoneStatus::Stopped))
+        {
+            // Picked up again by torrent-start.
+            info!("{}: download {}", t, "stopped".yellow());
         } else {
             warn!("{}: not all targets downloaded", t);
-            // Drop a failed orphan from tracking so a later watch-folder scan
-            // can retry it instead of it being suppressed forever (issue #34).
-            if t.is_orphan {
-                if let Some(file_id) = t.file_id {
-                    self.app_data.state.remove_orphan(file_id).await;
-                }
+            self.forget_failed(&t).await;
+        }
+        Ok(false)
+    }
+
+    /// Lets go of a transfer that couldn't be completed.
+    async fn forget_failed(&self, t: &Transfer) {
+        self.app_data.queue.remove(t.transfer_id);
+        // Drop a failed orphan from tracking so a later watch-folder scan
+        // can retry it instead of it being suppressed forever (issue #34).
+        if t.is_orphan {
+            if let Some(file_id) = t.file_id {
+                self.app_data.state.remove_orphan(file_id).await;
+            }
+        }
+    }
+
+    /// The `priority` configured for the *arr whose category `t` downloads to.
+    async fn category_priority(&self, t: &Transfer) -> i64 {
+        let Some(hash) = &t.hash else { return 0 };
+        let Some(state) = self.app_data.state.get_transfer(hash).await else {
+            return 0;
+        };
+        self.app_data
+            .config
+            .all_arrs()
+            .into_iter()
+            .find(|(_, _, arr)| arr.category.as_ref() == Some(&state.source_category))
+            .map_or(0, |(_, _, arr)| arr.priority)
+    }
+}
+
+/// Reserves the disk space `t` still needs, if the download filesystem can
+/// take it on top of `free_space_reserve_mb` and of what the other transfers
+/// downloading to it still need, so a full disk doesn't end in failed retries
+/// and half-written files. The space stays reserved until the download
+/// finished. Returns why the transfer has to wait if it can't.
+async fn reserve_space(
+    app_data: &AppData,
+    t: &Transfer,
+    targets: &[DownloadTarget],
+) -> Result<Option<String>> {
+    let Some(dir) = download_dir(targets) else {
+        return Ok(None);
+    };
+    loop {
+        let (generation, reason) = missing_space(app_data, t, targets, &dir).await?;
+        if reason.is_some() {
+            return Ok(reason);
+        }
+        let reservation = SpaceReservation {
+            dir: dir.clone(),
+            targets: targets.to_vec(),
+        };
+        if app_data
+            .state
+            .reserve_space(generation, t.transfer_id, reservation)
+        {
+            app_data
+                .state
+                .set_waiting_for_space(t.transfer_id, None)
+                .await;
+            return Ok(None);
+        }
+        // Another transfer reserved space in the meantime.
+    }
+}
+
+/// Waits, outside the orchestration workers, until `t` would fit on disk
+/// and hands it back to them. Gives up on a transfer that gets stopped. The
+/// transfer stays marked as processing meanwhile, so a `torrent-start` can't
+/// queue it a second time.
+async fn wait_for_space(
+    app_data: Data<AppData>,
+    tx: Sender<TransferMessage>,
+    t: Transfer,
+    targets: Vec<DownloadTarget>,
+) -> Result<()> {
+    let state = &app_data.state;
+    let Some(dir) = download_dir(&targets) else {
+        return Ok(());
+    };
+    loop {
+        sleep(Duration::from_secs(app_data.config.polling_interval.max(1))).await;
+        if let Some(hash) = &t.hash {
+            if state.is_stopped(hash).await {
+                info!("{}: download {}", t, "stopped".yellow());
+                state.set_waiting_for_space(t.transfer_id, None).await;
+                state.end_processing(t.transfer_id).await;
+                return Ok(());
             }
         }
-        Ok(())
+        match missing_space(&app_data, &t, &targets, &dir).await {
+            Ok((_, None)) => break,
+            Ok((_, Some(reason))) => {
+                state
+                    .set_waiting_for_space(t.transfer_id, Some(reason))
+                    .await
+            }
+            Err(e) => warn!("{}: {:#}", t, e),
+        }
+    }
+    info!("{}: enough disk space, queueing again", t);
+    tx.send(TransferMessage::QueuedForDownload(t)).await?;
+    Ok(())
+}
+
+/// Why `targets` don't fit into `dir` yet, if they don't, along with the
+/// generation of the reservations that was measured against.
+async fn missing_space(
+    app_data: &AppData,
+    t: &Transfer,
+    targets: &[DownloadTarget],
+    dir: &str,
+) -> Result<(u64, Option<String>)> {
+    // Measured without holding the reservations; `reserve_space` notices
+    // when they changed in the meantime.
+    let (generation, others) = app_data.state.space_reservations(t.transfer_id);
+    let needed = remaining_bytes(targets).await;
+    let mut reserved = app_data.config.free_space_reserve_mb * 1024 * 1024;
+    for other in &others {
+        if same_filesystem(&other.dir, dir).await {
+            reserved += remaining_bytes(&other.targets).await;
+        }
+    }
+    let available = utils::disk_space(dir)?.available;
+    if needed + reserved <= available {
+        return Ok((generation, None));
+    }
+    let reason = format!(
+        "Waiting for disk space: needs {} MiB, {} MiB free ({} MiB reserved)",
+        needed / 1024 / 1024,
+        available / 1024 / 1024,
+        reserved / 1024 / 1024
+    );
+    Ok((generation, Some(reason)))
+}
+
+/// The directory `targets` are downloaded into.
+fn download_dir(targets: &[DownloadTarget]) -> Option<String> {
+    let top_level = targets.iter().find(|t| t.top_level)?;
+    let dir = Path::new(&top_level.to).parent()?;
+    Some(dir.to_string_lossy().to_string())
+}
+
+/// Bytes still to be written for `targets`: the files not downloaded yet,
+/// less what their `.downloading` files already hold. Partial files are
+/// measured by their allocated blocks, since segmented downloads preallocate
+/// them sparsely.
+async fn remaining_bytes(targets: &[DownloadTarget]) -> u64 {
+    let mut remaining = 0;
+    for target in targets {
+        if target.target_type != TargetType::File || metadata(&target.to).await.is_ok() {
+            continue;
+        }
+        let size = u64::try_from(target.size).unwrap_or(0);
+        let written = match metadata(format!("{}.downloading", target.to)).await {
+            Ok(m) => m.blocks() * 512,
+            Err(_) => 0,
+        };
+        remaining += size.saturating_sub(written);
+    }
+    remaining
+}
+
+/// True if `a` and `b` are on the same filesystem, or if that can't be told.
+async fn same_filesystem(a: &str, b: &str) -> bool {
+    match (metadata(a).await, metadata(b).await) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev(),
+        _ => true,
     }
 }
 
@@ -152,39 +422,48 @@ async fn watch_for_import(
     // detected within a poll or two, so the default is generous.
     let import_timeout = Duration::from_secs(app_data.config.import_timeout_secs);
     let started = Instant::now();
+    let polling_interval = Duration::from_secs(app_data.config.polling_interval);
     loop {
-        if transfer.is_imported().await {
+        // Imports reported through the webhook are acted on right away;
+        // otherwise the history kept by `history::watch` is asked.
+        let reported = match &transfer.hash {
+            Some(hash) => app_data.state.webhook_import(hash).await,
+            None => None,
+        };
+        let imported =
+            reported.is_some_and(|files| covers(&transfer, &files)) || transfer.is_imported().await;
+        if imported {
             info!("{}: imported", transfer);
+            if let Err(e) = hook::run(&app_data, &transfer, Event::Imported).await {
+                warn!("{}: {:#}", transfer, e);
+            }
             let top_level_target = transfer.get_top_level();
 
-            match metadata(&top_level_target.to).await {
-                Ok(m) if m.is_dir() => {
-                    fs::remove_dir_all(&top_level_target.to).unwrap();
-                    info!("{}: deleted", &top_level_target);
-                }
-                Ok(m) if m.is_file() => {
-                    fs::remove_file(&top_level_target.to).unwrap();
-                    info!("{}: deleted", &top_level_target);
-                }
-                Ok(_) | Err(_) => {
-                    panic!("{}: no idea how to handle", &top_level_target)
-                }
+            let deleted = match metadata(&top_level_target.to).await {
+                Ok(m) if m.is_dir() => fs::remove_dir_all(&top_level_target.to),
+                Ok(m) if m.is_file() => fs::remove_file(&top_level_target.to),
+                Ok(_) => Err(std::io::Error::other("neither a file nor a directory")),
+                Err(e) => Err(e),
             };
+            match deleted {
+                Ok(()) => info!("{}: deleted", &top_level_target),
+                Err(e) => error!("{}: failed to delete: {}", &top_level_target, e),
+            }
+            // An archive that was the whole transfer sits next to the folder
+            // it was unpacked into.
+            for archive in transfer.targets.iter().flatten().filter(|t| t.archive) {
+                if fs::remove_file(&archive.to).is_ok() {
+                    info!("{}: deleted", archive);
+                }
+            }
             // An orphan has no put.io transfer to remove or seed, so finish it
             // here directly instead of routing an Imported message through a
             // worker (which may be busy downloading and never pick it up),
             // deleting the now-imported file from put.io (issue #34).
             if transfer.is_orphan {
-                if let Some(file_id) = transfer.file_id {
-                    match putio::delete_file(&app_data.config.putio.api_key, file_id).await {
-                        Ok(_) => info!("{}: deleted orphan from put.io", transfer),
-                        Err(e) => {
-                            warn!("{}: failed to delete orphan from put.io: {}", transfer, e)
-                        }
-                    }
-                    app_data.state.remove_orphan(file_id).await;
-                }
+                finish_orphan(&app_data, &transfer).await;
             } else {
+                app_data.queue.set(&transfer, Stage::Imported);
                 let m = transfer.clone();
                 tx.send(TransferMessage::Imported(m)).await?;
             }
@@ -199,13 +478,48 @@ async fn watch_for_import(
                  so its local/put.io copies won't be cleaned up automatically.",
                 transfer, import_timeout
             );
+            app_data.queue.remove(transfer.transfer_id);
             break;
         }
-        sleep(Duration::from_secs(app_data.config.polling_interval)).await;
+        app_data.state.wait_for_webhook(polling_interval).await;
+    }
+    if let Some(hash) = &transfer.hash {
+        app_data.state.clear_webhook_import(hash).await;
     }
     Ok(())
 }
 
+/// Deletes an imported orphan from put.io and stops tracking it.
+pub(super) async fn finish_orphan(app_data: &AppData, transfer: &Transfer) {
+    if let Some(file_id) = transfer.file_id {
+        match putio::delete_file(&app_data.config.putio.api_key, file_id).await {
+            Ok(_) => info!("{}: deleted orphan from put.io", transfer),
+            Err(e) => {
+                warn!("{}: failed to delete orphan from put.io: {}", transfer, e)
+            }
+        }
+        app_data.state.remove_orphan(file_id).await;
+    }
+    if let Some(hash) = &transfer.hash {
+        app_data.state.forget_transfer(hash).await;
+        app_data.download_queue.clear_bandwidth_priority(hash);
+    }
+    app_data.queue.remove(transfer.transfer_id);
+}
+
+/// True if `files` names every file of `transfer` the *arr is expected to
+/// import.
+fn covers(transfer: &Transfer, files: &HashSet<String>) -> bool {
+    let targets = transfer.importable_files();
+    !files.is_empty()
+        && !targets.is_empty()
+        && targets.iter().all(|t| {
+            Path::new(&t.to)
+                .file_name()
+                .is_some_and(|n| files.contains(n.to_string_lossy().as_ref()))
+        })
+}
+
 async fn watch_seeding(app_data: Data<AppData>, transfer: Transfer) -> Result<()> {
     info!("{}: watching seeding", transfer);
     loop {
@@ -232,6 +546,7 @@ async fn watch_seeding(app_data: Data<AppData>, transfer: Transfer) -> Result<()
         sleep(Duration::from_secs(app_data.config.polling_interval)).await;
     }
 
+    app_data.queue.remove(transfer.transfer_id);
     info!("{}: done seeding", transfer);
     Ok(())
 }
@@ -0,0 +1,172 @@
+use super::download::DownloadTargetMessage;
+use crate::services::transmission::QueueMove;
+use std::{cmp::Reverse, collections::HashMap, sync::Mutex};
+use tokio::sync::Notify;
+
+/// The download targets waiting for a download worker, handed out by
+/// priority instead of first come, first served:
+///
+/// 1. the transfer's bandwidth priority (`torrent-set` `bandwidthPriority`),
+/// 2. the priority configured for the transfer's *arr category,
+/// 3. the transfer's queue position, changed through `queue-move-*`,
+/// 4. the order targets were queued in.
+///
+/// New transfers are queued last, or with `smallest_first` ahead of the first
+/// larger transfer, so a single episode doesn't wait for a season pack.
+pub struct DownloadQueue {
+    inner: Mutex<Inner>,
+    notify: Notify,
+    smallest_first: bool,
+}
+
+#[derive(Default)]
+struct Inner {
+    pending: Vec<Pending>,
+    /// Hashes (and total size) of the transfers with pending targets, by queue
+    /// position.
+    order: Vec<(String, i64)>,
+    bandwidth_priority: HashMap<String, i64>,
+    seq: u64,
+}
+
+struct Pending {
+    msg: DownloadTargetMessage,
+    hash: String,
+    category_priority: i64,
+    seq: u64,
+}
+
+impl DownloadQueue {
+    pub fn new(smallest_first: bool) -> Self {
+        Self {
+            inner: Mutex::new(Inner::default()),
+            notify: Notify::new(),
+            smallest_first,
+        }
+    }
+
+    /// Queues the targets of the transfer with this hash. `size` is the
+    /// transfer's total size.
+    pub fn push(
+        &self,
+        hash: &str,
+        size: i64,
+        category_priority: i64,
+        msgs: Vec<DownloadTargetMessage>,
+    ) {
+        let hash = hash.to_lowercase();
+        let count = msgs.len();
+        {
+            let mut inner = self.inner.lock().unwrap();
+            if !inner.order.iter().any(|(h, _)| *h == hash) {
+                let position = if self.smallest_first {
+                    inner
+                        .order
+                        .iter()
+                        .position(|(_, s)| *s > size)
+                        .unwrap_or(inner.order.len())
+                } else {
+                    inner.order.len()
+                };
+                inner.order.insert(position, (hash.clone(), size));
+            }
+            for msg in msgs {
+                inner.seq += 1;
+                let seq = inner.seq;
+                inner.pending.push(Pending {
+                    msg,
+                    hash: hash.clone(),
+                    category_priority,
+                    seq,
+                });
+            }
+        }
+        for _ in 0..count {
+            self.notify.notify_one();
+        }
+    }
+
+    /// Waits for the next target to download.
+    pub async fn pop(&self) -> DownloadTargetMessage {
+        loop {
+            if let Some(msg) = self.try_pop() {
+                return msg;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    fn try_pop(&self) -> Option<DownloadTargetMessage> {
+        let mut inner = self.inner.lock().unwrap();
+        let index = {
+            let inner = &*inner;
+            let position = |hash: &str| inner.order.iter().position(|(h, _)| h == hash);
+            inner
+                .pending
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, p)| {
+                    (
+                        Reverse(inner.bandwidth_priority.get(&p.hash).copied().unwrap_or(0)),
+                        Reverse(p.category_priority),
+                        position(&p.hash),
+                        p.seq,
+                    )
+                })
+                .map(|(i, _)| i)?
+        };
+        let pending = inner.pending.remove(index);
+        if !inner.pending.iter().any(|p| p.hash == pending.hash) {
+            inner.order.retain(|(h, _)| *h != pending.hash);
+        }
+        Some(pending.msg)
+    }
+
+    /// Moves the transfer with this hash in the queue. Does nothing if none of
+    /// its targets are waiting.
+    pub fn move_transfer(&self, hash: &str, to: QueueMove) {
+        let hash = hash.to_lowercase();
+        let mut inner = self.inner.lock().unwrap();
+        let order = &mut inner.order;
+        let Some(position) = order.iter().position(|(h, _)| *h == hash) else {
+            return;
+        };
+        match to {
+            QueueMove::Top => {
+                let entry = order.remove(position);
+                order.insert(0, entry);
+            }
+            QueueMove::Bottom => {
+                let entry = order.remove(position);
+                order.push(entry);
+            }
+            QueueMove::Up if position > 0 => order.swap(position, position - 1),
+            QueueMove::Down if position + 1 < order.len() => order.swap(position, position + 1),
+            QueueMove::Up | QueueMove::Down => {}
+        }
+    }
+
+    /// Transmission's `bandwidthPriority` of the transfer with this hash:
+    /// -1 (low), 0 (normal) or 1 (high).
+    pub fn bandwidth_priority(&self, hash: &str) -> i64 {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .bandwidth_priority
+            .get(&hash.to_lowercase())
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn set_bandwidth_priority(&self, hash: &str, priority: i64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .bandwidth_priority
+            .insert(hash.to_lowercase(), priority.clamp(-1, 1));
+    }
+
+    /// Forgets the bandwidth priority of the transfer with this hash.
+    pub fn clear_bandwidth_priority(&self, hash: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.bandwidth_priority.remove(&hash.to_lowercase());
+    }
+}
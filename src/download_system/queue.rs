@@ -0,0 +1,253 @@
+use super::{
+    orchestration,
+    transfer::{DownloadTarget, Transfer, TransferMessage},
+};
+use crate::{services::putio::PutIOTransfer, state::OrphanFile, AppData};
+use actix_web::web::Data;
+use anyhow::{Context, Result};
+use async_channel::Sender;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// How far a transfer got through the download system.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Waiting for an orchestration worker.
+    Queued,
+    /// Its targets are being downloaded.
+    Downloading,
+    /// Downloaded, waiting for the *arr to import it.
+    Downloaded,
+    /// Imported, waiting for put.io to stop seeding it.
+    Imported,
+}
+
+/// A transfer as it's written to the queue file.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct QueueEntry {
+    transfer_id: u64,
+    name: String,
+    file_id: Option<i64>,
+    hash: Option<String>,
+    #[serde(default)]
+    is_orphan: bool,
+    targets: Option<Vec<DownloadTarget>>,
+    stage: Stage,
+}
+
+impl QueueEntry {
+    fn into_transfer(self, app_data: Data<AppData>) -> Transfer {
+        Transfer {
+            name: self.name,
+            file_id: self.file_id,
+            hash: self.hash,
+            transfer_id: self.transfer_id,
+            targets: self.targets,
+            app_data,
+            is_orphan: self.is_orphan,
+        }
+    }
+}
+
+/// The transfers moving through the download system, mirrored to a JSON file
+/// so a restart picks every one of them up at the stage it was in instead of
+/// rediscovering and re-listing everything from put.io. Partially downloaded
+/// files resume from their `.downloading` file as usual.
+pub struct Queue {
+    path: PathBuf,
+    entries: Mutex<BTreeMap<u64, QueueEntry>>,
+    /// Number of the latest snapshot of `entries`, and of the one on disk.
+    /// Snapshots are written off the runtime and may finish out of order; an
+    /// older one never overwrites a newer one.
+    snapshots: AtomicU64,
+    written: Arc<Mutex<u64>>,
+}
+
+impl Queue {
+    /// Reads the queue file at `path`, starting empty if there is none.
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let entries = match fs::read(&path) {
+            Ok(bytes) => match serde_json::from_slice(&bytes) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    warn!("queue: ignoring unreadable {}: {}", path.display(), e);
+                    BTreeMap::new()
+                }
+            },
+            Err(_) => BTreeMap::new(),
+        };
+        Self {
+            path,
+            entries: Mutex::new(entries),
+            snapshots: AtomicU64::new(0),
+            written: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    /// Records that `transfer` reached `stage`.
+    pub fn set(&self, transfer: &Transfer, stage: Stage) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(
+            transfer.transfer_id,
+            QueueEntry {
+                transfer_id: transfer.transfer_id,
+                name: transfer.name.clone(),
+                file_id: transfer.file_id,
+                hash: transfer.hash.clone(),
+                is_orphan: transfer.is_orphan,
+                targets: transfer.targets.clone(),
+                stage,
+            },
+        );
+        self.save(&entries);
+    }
+
+    /// Forgets a transfer that left the download system.
+    pub fn remove(&self, transfer_id: u64) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.remove(&transfer_id).is_some() {
+            self.save(&entries);
+        }
+    }
+
+    /// True if the transfer with this hash is downloaded and waiting for the
+    /// *arr to import it.
+    pub fn awaits_import(&self, hash: &str) -> bool {
+        self.entries.lock().unwrap().values().any(|e| {
+            e.stage == Stage::Downloaded
+                && e.hash
+                    .as_deref()
+                    .is_some_and(|h| h.eq_ignore_ascii_case(hash))
+        })
+    }
+
+    /// Writes a snapshot of `entries` to the queue file without blocking the
+    /// runtime. Called with the entries locked, so snapshots are numbered in
+    /// the order they were taken.
+    fn save(&self, entries: &BTreeMap<u64, QueueEntry>) {
+        let bytes = match serde_json::to_vec(entries) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                warn!("queue: cannot serialize: {}", e);
+                return;
+            }
+        };
+        let snapshot = self.snapshots.fetch_add(1, Ordering::SeqCst) + 1;
+        let path = self.path.clone();
+        let written = self.written.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut written = written.lock().unwrap();
+            if *written > snapshot {
+                return;
+            }
+            match write_atomically(&path, &bytes) {
+                Ok(()) => *written = snapshot,
+                Err(e) => warn!("queue: failed to write {}: {:#}", path.display(), e),
+            }
+        });
+    }
+}
+
+fn write_atomically(path: &Path, bytes: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, bytes).with_context(|| format!("cannot write {}", tmp.display()))?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Hands every transfer from the queue file back to the download system at
+/// the stage it was in. Transfers that disappeared from put.io in the
+/// meantime are dropped. Returns the ids of the restored transfers.
+pub async fn restore(
+    app_data: &Data<AppData>,
+    tx: &Sender<TransferMessage>,
+    active: &[PutIOTransfer],
+) -> Result<Vec<u64>> {
+    let entries: Vec<QueueEntry> = app_data
+        .queue
+        .entries
+        .lock()
+        .unwrap()
+        .values()
+        .cloned()
+        .collect();
+
+    let mut restored = vec![];
+    for entry in entries {
+        let id = entry.transfer_id;
+        if !entry.is_orphan && !active.iter().any(|t| t.id == id) {
+            info!("queue: {} is gone from put.io, dropping it", entry.name);
+            app_data.queue.remove(id);
+            continue;
+        }
+        let stage = entry.stage;
+        let transfer = entry.into_transfer(app_data.clone());
+        if transfer.is_orphan {
+            if let Some(orphan) = orphan_file(&transfer) {
+                app_data.state.add_orphan(orphan).await;
+            }
+        }
+        info!("{}: restored from queue ({:?})", transfer, stage);
+        let msg = match stage {
+            Stage::Queued | Stage::Downloading => TransferMessage::QueuedForDownload(transfer),
+            // Imported and deleted before the crash, but not recorded as
+            // such yet.
+            Stage::Downloaded if !has_local_files(&transfer) => {
+                warn!(
+                    "{}: downloaded files are gone, assuming it was imported",
+                    transfer
+                );
+                if transfer.is_orphan {
+                    orchestration::finish_orphan(app_data, &transfer).await;
+                    continue;
+                }
+                app_data.queue.set(&transfer, Stage::Imported);
+                TransferMessage::Imported(transfer)
+            }
+            Stage::Downloaded => {
+                app_data.state.mark_local_complete(id).await;
+                TransferMessage::Downloaded(transfer)
+            }
+            Stage::Imported => TransferMessage::Imported(transfer),
+        };
+        tx.send(msg).await?;
+        restored.push(id);
+    }
+    Ok(restored)
+}
+
+/// True if the transfer's top-level target is still on disk.
+fn has_local_files(transfer: &Transfer) -> bool {
+    transfer
+        .targets
+        .iter()
+        .flatten()
+        .find(|t| t.top_level)
+        .is_some_and(|t| Path::new(&t.to).exists())
+}
+
+/// Rebuilds the torrent-get record of a restored orphan from its targets.
+fn orphan_file(transfer: &Transfer) -> Option<OrphanFile> {
+    let targets = transfer.targets.as_ref()?;
+    let top_level = targets.iter().find(|t| t.top_level)?;
+    Some(OrphanFile {
+        file_id: transfer.file_id?,
+        name: transfer.name.clone(),
+        hash: transfer.hash.clone()?,
+        size: targets.iter().map(|t| t.size).sum(),
+        download_dir: Path::new(&top_level.to)
+            .parent()?
+            .to_string_lossy()
+            .to_string(),
+    })
+}
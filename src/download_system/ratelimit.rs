@@ -0,0 +1,77 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Bytes in a kB, the unit of Transmission's speed limits.
+const KILOBYTE: f64 = 1000.0;
+
+/// Token bucket capping download speed. The limit is in kB/s, like
+/// Transmission's `speed-limit-down`, and can be changed while downloads are
+/// running. The bucket holds at most one second worth of bytes, so a quiet
+/// period doesn't allow a long burst afterwards.
+pub struct RateLimiter {
+    limit: AtomicU64,
+    enabled: AtomicBool,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    /// Bytes that may be downloaded right away. Negative when callers have
+    /// borrowed ahead and are sleeping it off.
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u64, enabled: bool) -> Self {
+        Self {
+            limit: AtomicU64::new(limit),
+            enabled: AtomicBool::new(enabled),
+            bucket: Mutex::new(Bucket {
+                tokens: 0.0,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// The limit in kB/s, also when it's not enabled.
+    pub fn limit(&self) -> u64 {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    pub fn set_limit(&self, limit: u64) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Waits until `bytes` more may be downloaded. Returns right away when
+    /// the limiter is disabled or its limit is 0.
+    pub async fn acquire(&self, bytes: u64) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+            bucket.refilled_at = now;
+
+            let limit = self.limit();
+            if !self.is_enabled() || limit == 0 {
+                bucket.tokens = 0.0;
+                return;
+            }
+            let rate = limit as f64 * KILOBYTE;
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(rate) - bytes as f64;
+            if bucket.tokens >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-bucket.tokens / rate)
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
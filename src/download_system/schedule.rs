@@ -0,0 +1,128 @@
+use crate::AppData;
+use actix_web::web::Data;
+use anyhow::{anyhow, Context, Result};
+use chrono::{Datelike, Local, NaiveDateTime, NaiveTime, Weekday};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// How often the schedule is re-evaluated.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A time window during which downloads are slowed down or paused, like
+/// Transmission's alternative speed scheduler. Configured as `[[schedule]]`
+/// entries.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ScheduleWindow {
+    /// Days the window starts on ("mon", "tuesday", ...). Empty means every day.
+    #[serde(default)]
+    pub days: Vec<String>,
+    /// Local start time, "HH:MM".
+    pub start: String,
+    /// Local end time, "HH:MM". An end before the start runs past midnight.
+    pub end: String,
+    /// Download speed limit in kB/s while the window is active.
+    #[serde(default)]
+    pub speed_limit: Option<u64>,
+    /// Don't start or continue any download while the window is active.
+    #[serde(default)]
+    pub paused: bool,
+}
+
+impl ScheduleWindow {
+    fn times(&self) -> Result<(NaiveTime, NaiveTime)> {
+        let parse = |t: &str| {
+            NaiveTime::parse_from_str(t, "%H:%M").with_context(|| format!("invalid time {:?}", t))
+        };
+        Ok((parse(&self.start)?, parse(&self.end)?))
+    }
+
+    fn days(&self) -> Result<Vec<Weekday>> {
+        self.days
+            .iter()
+            .map(|d| d.parse().map_err(|_| anyhow!("invalid day {:?}", d)))
+            .collect()
+    }
+
+    /// True if the window covers `now`.
+    fn is_active(&self, now: NaiveDateTime) -> Result<bool> {
+        let (start, end) = self.times()?;
+        let days = self.days()?;
+        let starts_on = |day: Weekday| days.is_empty() || days.contains(&day);
+        let time = now.time();
+        let today = now.weekday();
+        Ok(if start <= end {
+            starts_on(today) && start <= time && time < end
+        } else {
+            // Runs past midnight: it either started today, or started
+            // yesterday and hasn't ended yet.
+            (starts_on(today) && time >= start) || (starts_on(today.pred()) && time < end)
+        })
+    }
+}
+
+/// Checks that every window parses, so mistakes surface at startup instead of
+/// silently never matching.
+pub fn validate(schedule: &[ScheduleWindow]) -> Result<()> {
+    for (i, window) in schedule.iter().enumerate() {
+        window
+            .times()
+            .and(window.days())
+            .with_context(|| format!("invalid schedule entry {}", i + 1))?;
+    }
+    Ok(())
+}
+
+/// Combined effect of the windows active at a point in time.
+#[derive(Debug, Default)]
+struct Restriction {
+    paused: bool,
+    speed_limit: Option<u64>,
+}
+
+fn restriction(schedule: &[ScheduleWindow], now: NaiveDateTime) -> Restriction {
+    schedule
+        .iter()
+        .filter(|w| w.is_active(now).unwrap_or(false))
+        .fold(Restriction::default(), |r, w| Restriction {
+            paused: r.paused || w.paused,
+            speed_limit: match (r.speed_limit, w.speed_limit) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
+        })
+}
+
+/// Applies the windows active right now to the download system.
+pub fn apply(app_data: &Data<AppData>) {
+    let r = restriction(&app_data.config.schedule, Local::now().naive_local());
+    let state = &app_data.state;
+
+    if state.set_schedule_paused(r.paused) != r.paused {
+        if r.paused {
+            info!("schedule: downloads paused");
+        } else {
+            info!("schedule: downloads resumed");
+        }
+    }
+
+    let limiter = state.schedule_limit();
+    let limit = r.speed_limit.unwrap_or(0);
+    if limiter.is_enabled() != r.speed_limit.is_some() || limiter.limit() != limit {
+        match r.speed_limit {
+            Some(limit) => info!("schedule: download speed limited to {} kB/s", limit),
+            None => info!("schedule: download speed limit lifted"),
+        }
+        limiter.set_limit(limit);
+        limiter.set_enabled(r.speed_limit.is_some());
+    }
+}
+
+/// Re-applies the schedule every [`CHECK_INTERVAL`].
+pub async fn watch(app_data: Data<AppData>) {
+    loop {
+        apply(&app_data);
+        sleep(CHECK_INTERVAL).await;
+    }
+}
@@ -0,0 +1,117 @@
+use super::transfer::{DownloadTarget, TargetType};
+use anyhow::{Context, Result};
+use file_owner::PathExt;
+use log::info;
+use nix::{errno::Errno, unistd::Uid};
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+/// Rewrites `targets` to download into `incomplete_directory` instead of their
+/// final location, like Transmission's incomplete-dir. Each transfer is staged
+/// under a directory named after its hash, so same-named releases don't
+/// collide. Files already at their final location (a transfer downloaded
+/// again after a restart or a failed verify) are left there, so they aren't
+/// fetched twice. Returns the staged targets, or None if there's no top-level
+/// target to stage under.
+pub fn stage(
+    targets: &[DownloadTarget],
+    incomplete_directory: &str,
+) -> Option<Vec<DownloadTarget>> {
+    let top_level = targets.iter().find(|t| t.top_level)?;
+    let final_dir = Path::new(&top_level.to).parent()?;
+    let staging_dir = Path::new(incomplete_directory).join(&top_level.transfer_hash);
+    targets
+        .iter()
+        .map(|t| {
+            if t.target_type == TargetType::File && Path::new(&t.to).exists() {
+                return Some(t.clone());
+            }
+            let relative = Path::new(&t.to).strip_prefix(final_dir).ok()?;
+            Some(DownloadTarget {
+                to: staging_dir.join(relative).to_string_lossy().to_string(),
+                ..t.clone()
+            })
+        })
+        .collect()
+}
+
+/// Moves a staged download from `from` to `to`, merging it file by file into
+/// a directory that's already there. A rename when both are on the same
+/// filesystem; otherwise the files are copied, synced to disk and only then
+/// removed from the staging directory, so an interrupted move can be retried.
+/// The transfer's staging directory is removed once it's empty.
+pub async fn move_into_place(from: String, to: String, uid: u32) -> Result<()> {
+    if from == to {
+        return Ok(());
+    }
+    tokio::task::spawn_blocking(move || {
+        if let Some(parent) = Path::new(&to).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        merge(Path::new(&from), Path::new(&to), uid)?;
+        if let Some(staging_dir) = Path::new(&from).parent() {
+            let _ = fs::remove_dir(staging_dir);
+        }
+        Ok(())
+    })
+    .await?
+}
+
+fn merge(from: &Path, to: &Path, uid: u32) -> Result<()> {
+    if from.is_dir() && to.is_dir() {
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            merge(&entry.path(), &to.join(entry.file_name()), uid)?;
+        }
+        return Ok(fs::remove_dir(from)?);
+    }
+    match fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(e) if e.raw_os_error() == Some(Errno::EXDEV as i32) => {
+            info!("{} is on another filesystem, copying", to.display());
+            copy_synced(from, to, uid)?;
+            remove(from)
+        }
+        Err(e) => {
+            Err(e).with_context(|| format!("cannot move {} to {}", from.display(), to.display()))
+        }
+    }
+}
+
+/// Copies a file or directory tree, fsyncing every file it writes.
+fn copy_synced(from: &Path, to: &Path, uid: u32) -> Result<()> {
+    if from.is_dir() {
+        if !to.exists() {
+            fs::create_dir(to)?;
+            chown(to, uid)?;
+        }
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            let target: PathBuf = to.join(entry.file_name());
+            copy_synced(&entry.path(), &target, uid)?;
+        }
+    } else {
+        fs::copy(from, to).with_context(|| format!("cannot copy {}", from.display()))?;
+        File::open(to)?.sync_all()?;
+        chown(to, uid)?;
+    }
+    Ok(())
+}
+
+fn chown(path: &Path, uid: u32) -> Result<()> {
+    if Uid::effective().is_root() {
+        path.set_owner(uid)?;
+    }
+    Ok(())
+}
+
+fn remove(path: &Path) -> Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
@@ -1,8 +1,6 @@
+use super::{extract, queue::Stage};
 use crate::{
-    services::{
-        arr::ArrApp,
-        putio::{self, PutIOTransfer},
-    },
+    services::putio::{self, PutIOTransfer},
     state::OrphanFile,
     AppData,
 };
@@ -11,7 +9,7 @@ use anyhow::{Context, Result};
 use async_channel::Sender;
 use async_recursion::async_recursion;
 use colored::*;
-use log::{debug, error, info, warn};
+use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashSet,
@@ -37,65 +35,28 @@ pub struct Transfer {
 }
 
 impl Transfer {
+    /// True if the *arrs imported every file of the transfer, according to
+    /// the history kept by [`super::history::watch`].
     pub async fn is_imported(&self) -> bool {
-        let targets = self.targets.as_ref().unwrap().clone();
-        let apps: Vec<ArrApp> = self
-            .app_data
-            .config
-            .all_arrs()
-            .into_iter()
-            .map(|(name, kind, c)| ArrApp::new(name, kind, c))
-            .collect();
+        let history = &self.app_data.history;
+        let hash = self.hash.as_deref();
+        let known = hash.is_some_and(|h| history.knows(h));
+        let files = self.importable_files();
+        !files.is_empty() && files.iter().all(|t| history.is_imported(hash, known, t))
+    }
 
-        let targets = targets
-            .into_iter()
-            .filter(|t| t.target_type == TargetType::File)
-            .collect::<Vec<DownloadTarget>>();
-
-        let mut results = Vec::<bool>::new();
-        for target in targets {
-            let mut service_results = vec![];
-            for app in &apps {
-                // Only ask an *arr about files matching its media type.
-                if let Some(mt) = &target.media_type {
-                    if *mt != app.kind.media_type() {
-                        continue;
-                    }
-                }
-                let service_result = match app.check_imported(&target.to).await {
-                    Ok(r) => r,
-                    Err(e) => {
-                        // A misconfigured/unreachable *arr fails for every
-                        // transfer on every poll; throttle the log so it doesn't
-                        // fill the disk over time (issue #21). Key on the app's
-                        // existing name (no allocation on the suppressed path).
-                        if self.app_data.state.should_log_arr_error(&app.name).await {
-                            error!(
-                                "Error retrieving history from {} (suppressing repeats for {:?}): {}",
-                                app,
-                                crate::state::StateManager::ARR_ERROR_LOG_INTERVAL,
-                                e
-                            );
-                        }
-                        false
-                    }
-                };
-                if service_result {
-                    info!(
-                        "{}: found imported by {}",
-                        &target,
-                        app.to_string().bright_blue()
-                    );
-                }
-                service_results.push(service_result)
-            }
-            // If no service was eligible for this target, treat it as imported
-            // (otherwise an audio file with no Lidarr would block forever).
-            let any_imported = service_results.is_empty() || service_results.into_iter().any(|x| x);
-            results.push(any_imported);
+    /// The files the *arrs are expected to import. Empty while an archive is
+    /// still to be unpacked, since its contents aren't known yet, so that a
+    /// transfer is never taken for imported (and deleted) before it was
+    /// downloaded.
+    pub fn importable_files(&self) -> Vec<&DownloadTarget> {
+        let targets = self.targets.iter().flatten();
+        if targets.clone().any(|t| t.archive && !t.extracted) {
+            return vec![];
         }
-        // Check if all targets have been imported
-        results.into_iter().all(|x| x)
+        targets
+            .filter(|t| t.target_type == TargetType::File && !t.archive)
+            .collect()
     }
 
     pub async fn get_download_targets(&self) -> Result<Vec<DownloadTarget>> {
@@ -114,7 +75,73 @@ impl Transfer {
         let file_id = self.file_id.context("transfer has no file_id")?;
         let default = "0000".to_string();
         let hash = self.hash.as_ref().unwrap_or(&default).as_str();
-        recurse_download_targets(&self.app_data, file_id, hash, base_path, true).await
+        let books = self.wants_books().await;
+        recurse_download_targets(&self.app_data, file_id, hash, base_path, books, true).await
+    }
+
+    /// True if the transfer was added by a Readarr, so its books are
+    /// downloaded and checked for an import. In any other transfer they're
+    /// extras nobody imports.
+    pub async fn wants_books(&self) -> bool {
+        let Some(hash) = &self.hash else { return false };
+        match self.app_data.state.get_transfer(hash).await {
+            Some(state) => self
+                .app_data
+                .config
+                .is_book_category(&state.source_category),
+            None => false,
+        }
+    }
+
+    /// Re-checks the local copy of a downloaded transfer against the file sizes
+    /// on put.io. Missing or truncated files are removed and the transfer is
+    /// queued again so they get re-downloaded. A transfer whose local copy is
+    /// gone entirely has already been imported and cleaned up, and is left
+    /// alone.
+    pub async fn verify(&self) -> Result<()> {
+        info!("{}: verifying local files", self);
+        let targets = self.get_download_targets().await?;
+        let top_level = targets
+            .iter()
+            .find(|t| t.top_level)
+            .context("transfer has no downloadable targets")?;
+        if !Path::new(&top_level.to).exists() {
+            info!("{}: no local copy to verify", self);
+            return Ok(());
+        }
+
+        let mut damaged = 0;
+        for target in targets.iter().filter(|t| t.target_type == TargetType::File) {
+            match tokio::fs::metadata(&target.to).await {
+                Ok(m) if target.size <= 0 || m.len() == target.size as u64 => {}
+                Ok(m) => {
+                    warn!(
+                        "{}: local size {} doesn't match put.io size {}",
+                        target,
+                        m.len(),
+                        target.size
+                    );
+                    tokio::fs::remove_file(&target.to).await?;
+                    damaged += 1;
+                }
+                Err(_) => {
+                    warn!("{}: missing locally", target);
+                    damaged += 1;
+                }
+            }
+        }
+
+        if damaged > 0 {
+            info!("{}: {} file(s) damaged, downloading again", self, damaged);
+            self.app_data
+                .state
+                .clear_local_complete(self.transfer_id)
+                .await;
+            self.app_data.state.request_requeue(self.transfer_id).await;
+        } else {
+            info!("{}: verified", self);
+        }
+        Ok(())
     }
 
     pub fn get_top_level(&self) -> DownloadTarget {
@@ -175,6 +202,7 @@ async fn recurse_download_targets(
     file_id: i64,
     hash: &str,
     override_base_path: Option<String>,
+    books: bool,
     top_level: bool,
 ) -> Result<Vec<DownloadTarget>> {
     // Check if we have stored state for this transfer to get the correct download directory
@@ -182,7 +210,10 @@ async fn recurse_download_targets(
         path
     } else {
         // Try to get the download directory from state, fallback to default
-        app_data.state.get_download_dir_for_transfer(hash, &app_data.config.download_directory).await
+        app_data
+            .state
+            .get_download_dir_for_transfer(hash, &app_data.config.download_directory)
+            .await
     };
     let mut targets = Vec::<DownloadTarget>::new();
     let response = putio::list_files(&app_data.config.putio.api_key, file_id).await?;
@@ -207,6 +238,10 @@ async fn recurse_download_targets(
                     top_level,
                     transfer_hash: hash.to_string(),
                     media_type: None,
+                    size: 0,
+                    crc32: None,
+                    archive: false,
+                    extracted: false,
                 });
 
                 for file in response.files {
@@ -216,6 +251,7 @@ async fn recurse_download_targets(
                             file.id,
                             hash,
                             Some(new_base_path.clone()),
+                            books,
                             false,
                         )
                         .await?,
@@ -223,7 +259,12 @@ async fn recurse_download_targets(
                 }
             }
         }
-        "VIDEO" | "AUDIO" => {
+        // Books only for a Readarr's transfers; otherwise they're extras of
+        // a video release.
+        file_type
+            if matches!(file_type, "VIDEO" | "AUDIO")
+                || (books && MediaType::is_book(&response.parent.name)) =>
+        {
             // Get download URL for file
             let url = putio::url(&app_data.config.putio.api_key, response.parent.id).await?;
             targets.push(DownloadTarget {
@@ -232,14 +273,34 @@ async fn recurse_download_targets(
                 to,
                 top_level,
                 transfer_hash: hash.to_string(),
-                media_type: MediaType::from_putio(response.parent.file_type.as_str()),
+                media_type: MediaType::from_putio(file_type, &response.parent.name, books),
+                size: response.parent.size,
+                crc32: response.parent.crc32,
+                archive: false,
+                extracted: false,
+            });
+        }
+        // Only archives putioarr can unpack itself; anything else would just
+        // sit there.
+        "ARCHIVE"
+            if app_data.config.extract_archives && extract::is_supported(&response.parent.name) =>
+        {
+            let url = putio::url(&app_data.config.putio.api_key, response.parent.id).await?;
+            targets.push(DownloadTarget {
+                from: Some(url),
+                target_type: TargetType::File,
+                to,
+                top_level,
+                transfer_hash: hash.to_string(),
+                media_type: None,
+                size: response.parent.size,
+                crc32: response.parent.crc32,
+                archive: true,
+                extracted: false,
             });
         }
         other => {
-            debug!(
-                "{}: skipping file type {}",
-                response.parent.name, other
-            );
+            debug!("{}: skipping file type {}", response.parent.name, other);
         }
     }
 
@@ -257,16 +318,33 @@ pub enum TransferMessage {
 pub enum MediaType {
     Audio,
     Video,
+    /// Ebooks and audiobooks, imported by Readarr.
+    Book,
 }
 
+/// Extensions of ebooks, and of audiobooks put.io lists as plain audio.
+const BOOK_EXTENSIONS: &[&str] = &["epub", "mobi", "azw3", "pdf", "m4b"];
+
 impl MediaType {
-    pub fn from_putio(file_type: &str) -> Option<Self> {
+    /// The media type of a file put.io lists as `file_type`. Books are only
+    /// told apart when `books` is set, i.e. for a Readarr's transfers.
+    pub fn from_putio(file_type: &str, name: &str, books: bool) -> Option<Self> {
+        if books && Self::is_book(name) {
+            return Some(Self::Book);
+        }
         match file_type {
             "AUDIO" => Some(Self::Audio),
             "VIDEO" => Some(Self::Video),
             _ => None,
         }
     }
+
+    /// True if `name` has the extension of an ebook or audiobook.
+    pub fn is_book(name: &str) -> bool {
+        Path::new(name)
+            .extension()
+            .is_some_and(|e| BOOK_EXTENSIONS.contains(&e.to_string_lossy().to_lowercase().as_str()))
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -277,6 +355,20 @@ pub struct DownloadTarget {
     pub top_level: bool,
     pub transfer_hash: String,
     pub media_type: Option<MediaType>,
+    /// Size of the file on put.io in bytes (0 for directories).
+    #[serde(default)]
+    pub size: i64,
+    /// CRC32 put.io reports for the file, checked once it's downloaded.
+    #[serde(default)]
+    pub crc32: Option<String>,
+    /// An archive to unpack once the transfer is downloaded. Removed after
+    /// the import instead of being checked for one.
+    #[serde(default)]
+    pub archive: bool,
+    /// Set on an archive once it has been unpacked and its files added as
+    /// targets.
+    #[serde(default)]
+    pub extracted: bool,
 }
 
 impl Display for DownloadTarget {
@@ -323,16 +415,28 @@ pub async fn produce_transfers(app_data: Data<AppData>, tx: Sender<TransferMessa
         Duration::from_secs(app_data.config.watch_folder_interval_secs.max(1));
     let mut last_orphan_scan: Option<Instant> = None;
 
+    let transfers = putio::list_transfers(&app_data.config.putio.api_key)
+        .await?
+        .transfers;
+
+    // Hashes on put.io as of the last poll, to notice transfers leaving it.
+    let mut on_putio = putio_hashes(&transfers);
+
+    // Pick up everything that was in flight when we stopped where it left off.
+    seen.extend(super::queue::restore(&app_data, &tx, &transfers).await?);
+
+    // Without the history every transfer would look not imported yet.
+    app_data.history.wait_loaded().await;
     info!("Checking unfinished transfers");
     // We only need to check if something has been imported. Just by looking at the filesystem we
     // can't determine if a transfer has been imported and removed or hasn't been downloaded.
     // This avoids downloading a tranfer that has already been imported. In case there is a download,
     // but it wasn't (completely) imported, we will attempt a (partial) download. Files that have
     // been completed downloading will be skipped.
-    for putio_transfer in &putio::list_transfers(&app_data.config.putio.api_key)
-        .await?
-        .transfers
-    {
+    for putio_transfer in &transfers {
+        if seen.contains(&putio_transfer.id) {
+            continue;
+        }
         let name = putio_transfer.name.clone().unwrap_or("??".to_string());
         let mut transfer = Transfer::from(app_data.clone(), putio_transfer);
         if putio_transfer.is_downloadable() && is_managed(&app_data, putio_transfer).await {
@@ -347,6 +451,7 @@ pub async fn produce_transfers(app_data: Data<AppData>, tx: Sender<TransferMessa
             if transfer.is_imported().await {
                 info!("{}: already imported", &transfer);
                 seen.push(transfer.transfer_id);
+                app_data.queue.set(&transfer, Stage::Imported);
                 tx.send(TransferMessage::Imported(transfer)).await?;
             } else {
                 info!("{}: not imported yet", &transfer);
@@ -362,6 +467,12 @@ pub async fn produce_transfers(app_data: Data<AppData>, tx: Sender<TransferMessa
         if let Ok(list_transfer_response) =
             putio::list_transfers(&app_data.config.putio.api_key).await
         {
+            // Forget transfers that were restarted or failed verification, so
+            // they are picked up again below.
+            for id in app_data.state.take_requeue().await {
+                seen.retain(|s| *s != id);
+            }
+
             for putio_transfer in &list_transfer_response.transfers {
                 if seen.contains(&putio_transfer.id) || !putio_transfer.is_downloadable() {
                     continue;
@@ -381,6 +492,7 @@ pub async fn produce_transfers(app_data: Data<AppData>, tx: Sender<TransferMessa
                 }
 
                 info!("{}: ready for download", transfer);
+                app_data.queue.set(&transfer, Stage::Queued);
                 tx.send(TransferMessage::QueuedForDownload(transfer))
                     .await?;
                 seen.push(putio_transfer.id);
@@ -394,6 +506,14 @@ pub async fn produce_transfers(app_data: Data<AppData>, tx: Sender<TransferMessa
                 .collect();
             seen.retain(|t| active_ids.contains(t));
 
+            // Forget what's kept in memory about transfers that left put.io.
+            let hashes = putio_hashes(&list_transfer_response.transfers);
+            for gone in on_putio.difference(&hashes) {
+                app_data.state.forget_transfer(gone).await;
+                app_data.download_queue.clear_bandwidth_priority(gone);
+            }
+            on_putio = hashes;
+
             // Pull orphaned files from the configured watch folders (completed
             // files whose transfer record no longer exists — see issue #34),
             // throttled so it doesn't list every folder on every poll.
@@ -424,6 +544,14 @@ pub async fn produce_transfers(app_data: Data<AppData>, tx: Sender<TransferMessa
     }
 }
 
+/// Lowercase hashes of `transfers`.
+fn putio_hashes(transfers: &[PutIOTransfer]) -> HashSet<String> {
+    transfers
+        .iter()
+        .filter_map(|t| t.hash.as_ref().map(|h| h.to_lowercase()))
+        .collect()
+}
+
 /// Heuristic: does this name look like a TV episode (SxxExx, or "Season")?
 /// Used to route an orphaned file to the Sonarr vs Radarr category folder.
 fn looks_like_episode(name: &str) -> bool {
@@ -474,7 +602,10 @@ async fn scan_watch_folders(
         for file in &resp.files {
             // Only media (and folders that may contain media); skip stray
             // images/nfos and anything with an unusable (negative) id.
-            if file.id < 0 || !matches!(file.file_type.as_str(), "FOLDER" | "VIDEO" | "AUDIO") {
+            let archive = app_data.config.extract_archives && file.file_type == "ARCHIVE";
+            if file.id < 0
+                || !(archive || matches!(file.file_type.as_str(), "FOLDER" | "VIDEO" | "AUDIO"))
+            {
                 continue;
             }
             // Skip the result of an active transfer (handled the normal way) and
@@ -518,7 +649,10 @@ async fn scan_watch_folders(
 
             if transfer.is_imported().await {
                 // Already imported by the *arr — just clean it off put.io.
-                info!("{}: orphan already imported, deleting from put.io", transfer);
+                info!(
+                    "{}: orphan already imported, deleting from put.io",
+                    transfer
+                );
                 if let Err(e) = putio::delete_file(api_key, file.id).await {
                     warn!(
                         "{}: failed to delete imported orphan from put.io: {}",
@@ -532,6 +666,7 @@ async fn scan_watch_folders(
             // Reuse the hash already derived by `from_orphan` so there's a single
             // source of truth for the synthetic hash.
             let hash = transfer.hash.clone().unwrap_or_default();
+            app_data.queue.set(&transfer, Stage::Queued);
             // Only start tracking/reporting the orphan once it's actually been
             // queued, so a failed send can't leave it advertised via torrent-get
             // as a download that never happens.
@@ -0,0 +1,418 @@
+use crate::{
+    http::handlers::{
+        add_torrent, category_download_dir, known_categories, list_torrents, remove_torrents,
+    },
+    services::transmission::{
+        QueueMove, TorrentId, TorrentIds, TorrentSource, TransmissionTorrent,
+        TransmissionTorrentStatus,
+    },
+    AppData,
+};
+use actix_web::{cookie::Cookie, post, web, HttpRequest, HttpResponse};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Cookie carrying the Deluge Web UI session.
+const SESSION_COOKIE: &str = "_session_id";
+
+/// Version reported through `daemon.info`.
+const DELUGE_VERSION: &str = "2.1.1";
+
+/// Deluge's error codes.
+const ERROR_NOT_AUTHENTICATED: i64 = 1;
+const ERROR_UNKNOWN_METHOD: i64 = 2;
+const ERROR_CALL_FAILED: i64 = 3;
+
+#[derive(Deserialize, Debug)]
+pub struct DelugeRequest {
+    pub method: String,
+    #[serde(default)]
+    pub params: Vec<Value>,
+    pub id: Value,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DelugeError {
+    pub message: String,
+    pub code: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DelugeResponse {
+    pub id: Value,
+    pub result: Value,
+    pub error: Option<DelugeError>,
+}
+
+/// Emulates the Deluge Web JSON-RPC methods the *arrs call. Deluge labels
+/// (from its Label plugin) map to putioarr categories.
+#[post("/json")]
+pub(crate) async fn json_rpc(
+    req: HttpRequest,
+    payload: web::Json<DelugeRequest>,
+    app_data: web::Data<AppData>,
+) -> HttpResponse {
+    let payload = payload.into_inner();
+
+    if payload.method == "auth.login" {
+        let password = payload.params.first().and_then(Value::as_str);
+        if password != Some(app_data.config.password.as_str()) {
+            return respond(payload.id, Ok(json!(false)));
+        }
+        return HttpResponse::Ok()
+            .cookie(
+                Cookie::build(SESSION_COOKIE, app_data.deluge_session.current())
+                    .path("/json")
+                    .http_only(true)
+                    .finish(),
+            )
+            .json(DelugeResponse {
+                id: payload.id,
+                result: json!(true),
+                error: None,
+            });
+    }
+
+    let authenticated = req
+        .cookie(SESSION_COOKIE)
+        .is_some_and(|c| app_data.deluge_session.is_valid(c.value()));
+    if !authenticated {
+        return error(payload.id, "Not authenticated", ERROR_NOT_AUTHENTICATED);
+    }
+
+    let result = match dispatch(&payload.method, &payload.params, &app_data).await {
+        Some(result) => result,
+        None => return error(payload.id, "Unknown method", ERROR_UNKNOWN_METHOD),
+    };
+    if let Err(e) = &result {
+        error!("{}: {:#}", payload.method, e);
+    }
+    respond(payload.id, result)
+}
+
+fn respond(id: Value, result: Result<Value>) -> HttpResponse {
+    match result {
+        Ok(result) => HttpResponse::Ok().json(DelugeResponse {
+            id,
+            result,
+            error: None,
+        }),
+        Err(e) => error(id, &format!("{:#}", e), ERROR_CALL_FAILED),
+    }
+}
+
+fn error(id: Value, message: &str, code: i64) -> HttpResponse {
+    HttpResponse::Ok().json(DelugeResponse {
+        id,
+        result: Value::Null,
+        error: Some(DelugeError {
+            message: message.to_string(),
+            code,
+        }),
+    })
+}
+
+/// Runs a method. Returns `None` for methods we don't know.
+async fn dispatch(
+    method: &str,
+    params: &[Value],
+    app_data: &web::Data<AppData>,
+) -> Option<Result<Value>> {
+    let api_token = &app_data.config.putio.api_key;
+    let result = match method {
+        "auth.check_session" | "web.connected" => Ok(json!(true)),
+        // putioarr is its own "daemon", so there's only ever one host and
+        // it's always connected.
+        "web.get_hosts" => Ok(json!([["putioarr", "127.0.0.1", 58846, "Connected"]])),
+        "web.get_host_status" => Ok(json!(["putioarr", "Connected", DELUGE_VERSION])),
+        "web.connect" | "web.disconnect" => Ok(Value::Null),
+        "daemon.info" | "daemon.get_version" => Ok(json!(DELUGE_VERSION)),
+        "core.get_config" => Ok(config(app_data)),
+        "core.get_config_value" => param_str(params, 0).map(|key| config(app_data)[key].clone()),
+        "core.get_enabled_plugins" | "core.get_available_plugins" => Ok(json!(["Label"])),
+        "core.enable_plugin" => Ok(json!(true)),
+        // Seeding happens on put.io; nothing to set locally.
+        "core.set_torrent_options" => Ok(Value::Null),
+        "core.queue_top" => queue_move(params, QueueMove::Top, app_data),
+        "core.queue_bottom" => queue_move(params, QueueMove::Bottom, app_data),
+        "core.queue_up" => queue_move(params, QueueMove::Up, app_data),
+        "core.queue_down" => queue_move(params, QueueMove::Down, app_data),
+        "core.get_torrents_status" => {
+            torrents_status(params.first(), params.get(1), app_data).await
+        }
+        "web.update_ui" => torrents_status(params.get(1), params.first(), app_data)
+            .await
+            .map(|torrents| json!({ "torrents": torrents, "connected": true, "stats": {} })),
+        "core.add_torrent_magnet" => match param_str(params, 0) {
+            Ok(uri) => add(TorrentSource::Url(uri.to_string()), params.get(1), app_data).await,
+            Err(e) => Err(e),
+        },
+        "core.add_torrent_file" => {
+            let decoded = param_str(params, 1).and_then(|b64| {
+                base64::engine::general_purpose::STANDARD
+                    .decode(b64)
+                    .context("invalid or corrupt torrent file")
+            });
+            match decoded {
+                Ok(bytes) => add(TorrentSource::Metainfo(bytes), params.get(2), app_data).await,
+                Err(e) => Err(e),
+            }
+        }
+        "core.remove_torrent" => match param_str(params, 0) {
+            Ok(hash) => {
+                let ids = TorrentIds::Single(TorrentId::Hash(hash.to_string()));
+                let remove_data = params.get(1).and_then(Value::as_bool).unwrap_or(false);
+                remove_torrents(api_token, Some(&ids), remove_data, app_data)
+                    .await
+                    .map(|_| json!(true))
+            }
+            Err(e) => Err(e),
+        },
+        "core.remove_torrents" => {
+            let hashes = params
+                .first()
+                .and_then(Value::as_array)
+                .map(|hashes| {
+                    hashes
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(|h| TorrentId::Hash(h.to_string()))
+                        .collect::<Vec<_>>()
+                })
+                .context("expected a list of torrent ids");
+            match hashes {
+                Ok(hashes) => {
+                    let remove_data = params.get(1).and_then(Value::as_bool).unwrap_or(false);
+                    let ids = TorrentIds::List(hashes);
+                    remove_torrents(api_token, Some(&ids), remove_data, app_data)
+                        .await
+                        .map(|_| json!([]))
+                }
+                Err(e) => Err(e),
+            }
+        }
+        "label.get_labels" => Ok(json!(known_categories(app_data)
+            .await
+            .into_keys()
+            .collect::<Vec<_>>())),
+        "label.add" => match param_str(params, 0) {
+            Ok(label) => add_label(label, app_data).await,
+            Err(e) => Err(e),
+        },
+        "label.set_torrent" => match (param_str(params, 0), param_str(params, 1)) {
+            (Ok(hash), Ok(label)) => set_label(hash, label, app_data).await,
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        },
+        _ => return None,
+    };
+    Some(result)
+}
+
+fn param_str(params: &[Value], index: usize) -> Result<&str> {
+    params
+        .get(index)
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("expected a string as parameter {}", index))
+}
+
+fn config(app_data: &web::Data<AppData>) -> Value {
+    json!({
+        "download_location": app_data.config.download_directory,
+        "move_completed": false,
+        "move_completed_path": app_data.config.download_directory,
+        "stop_seed_at_ratio": false,
+        "stop_seed_ratio": 0.0,
+        "remove_seed_at_ratio": false,
+    })
+}
+
+/// Adds a torrent without a category; the *arrs assign their label right
+/// after with `label.set_torrent`. Returns the torrent's hash.
+async fn add(
+    source: TorrentSource,
+    options: Option<&Value>,
+    app_data: &web::Data<AppData>,
+) -> Result<Value> {
+    let hash = add_torrent(
+        &app_data.config.putio.api_key,
+        &source,
+        "default",
+        &[],
+        app_data,
+    )
+    .await?;
+    // The torrent is on put.io already, so don't fail the call (the *arr
+    // would retry and add it twice). Deluge returns null when it has no id
+    // to hand back either.
+    let Some(hash) = hash else {
+        warn!("core.add_torrent: added, but could not determine the torrent's hash");
+        return Ok(Value::Null);
+    };
+    if options
+        .and_then(|o| o.get("add_paused"))
+        .and_then(Value::as_bool)
+        .unwrap_or(false)
+    {
+        app_data.state.stop_transfer(&hash).await;
+    }
+    Ok(json!(hash))
+}
+
+async fn add_label(label: &str, app_data: &web::Data<AppData>) -> Result<Value> {
+    let label = label.to_lowercase();
+    let valid = label
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+    if label.is_empty() || label == "default" || !valid {
+        bail!("Invalid label name: {}", label);
+    }
+    if known_categories(app_data).await.contains_key(&label) {
+        bail!("Label already exists");
+    }
+    info!("label.add: {}", label);
+    app_data.state.add_category(label, String::new()).await?;
+    Ok(Value::Null)
+}
+
+/// Moves the torrents listed in the first parameter in the download queue.
+fn queue_move(params: &[Value], to: QueueMove, app_data: &web::Data<AppData>) -> Result<Value> {
+    let hashes = params
+        .first()
+        .and_then(Value::as_array)
+        .context("expected a list of torrent ids")?;
+    for hash in hashes.iter().filter_map(Value::as_str) {
+        info!("queue move: hash={} to={:?}", hash, to);
+        app_data.download_queue.move_transfer(hash, to);
+    }
+    Ok(Value::Null)
+}
+
+async fn set_label(hash: &str, label: &str, app_data: &web::Data<AppData>) -> Result<Value> {
+    let category = if label.is_empty() {
+        "default".to_string()
+    } else if known_categories(app_data).await.contains_key(label) {
+        label.to_string()
+    } else {
+        bail!("Unknown Label");
+    };
+    info!("label.set_torrent: hash={} label={}", hash, category);
+    let download_dir = category_download_dir(&category, app_data).await;
+    app_data
+        .state
+        .add_transfer(hash.to_lowercase(), category, download_dir, Vec::new())
+        .await?;
+    Ok(Value::Null)
+}
+
+/// Builds the `{hash: {field: value}}` map Deluge returns for torrent status
+/// calls. `filter` may restrict it by `id` (hash or list of hashes) or
+/// `label`; `fields` restricts the returned keys.
+async fn torrents_status(
+    filter: Option<&Value>,
+    fields: Option<&Value>,
+    app_data: &web::Data<AppData>,
+) -> Result<Value> {
+    let ids = filter.and_then(|f| f.get("id")).map(|id| match id {
+        Value::Array(ids) => TorrentIds::List(
+            ids.iter()
+                .filter_map(Value::as_str)
+                .map(|h| TorrentId::Hash(h.to_string()))
+                .collect(),
+        ),
+        other => TorrentIds::Single(TorrentId::Hash(other.as_str().unwrap_or("").to_string())),
+    });
+    let label_filter = filter
+        .and_then(|f| f.get("label"))
+        .and_then(Value::as_str)
+        .map(str::to_string);
+    let fields: Vec<String> = fields
+        .and_then(Value::as_array)
+        .map(|f| {
+            f.iter()
+                .filter_map(Value::as_str)
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+    let wants_name = fields.is_empty() || fields.iter().any(|f| f == "name");
+
+    let torrents = list_torrents(
+        &app_data.config.putio.api_key,
+        ids.as_ref(),
+        wants_name,
+        app_data,
+    )
+    .await;
+
+    let mut out = serde_json::Map::new();
+    for tt in torrents {
+        let Some(hash) = tt.hash_string.clone().map(|h| h.to_lowercase()) else {
+            continue;
+        };
+        let label = match app_data.state.get_transfer(&hash).await {
+            Some(s) if s.source_category != "default" => s.source_category,
+            _ => String::new(),
+        };
+        if label_filter.as_ref().is_some_and(|l| *l != label) {
+            continue;
+        }
+        let mut status = deluge_status(tt, &hash, label);
+        if !fields.is_empty() {
+            if let Some(obj) = status.as_object_mut() {
+                obj.retain(|k, _| fields.iter().any(|f| f == k));
+            }
+        }
+        out.insert(hash, status);
+    }
+    Ok(Value::Object(out))
+}
+
+fn deluge_status(tt: TransmissionTorrent, hash: &str, label: String) -> Value {
+    let state = if tt.error_string.as_deref().is_some_and(|e| !e.is_empty()) {
+        "Error"
+    } else {
+        match tt.status {
+            TransmissionTorrentStatus::Stopped => "Paused",
+            TransmissionTorrentStatus::CheckWait | TransmissionTorrentStatus::Check => "Checking",
+            TransmissionTorrentStatus::Queued => "Queued",
+            TransmissionTorrentStatus::Downloading => "Downloading",
+            TransmissionTorrentStatus::SeedingWait | TransmissionTorrentStatus::Seeding => {
+                "Seeding"
+            }
+        }
+    };
+    let total_done = tt.total_size - tt.left_until_done;
+    let progress = if tt.total_size > 0 {
+        total_done as f64 * 100.0 / tt.total_size as f64
+    } else if tt.is_finished {
+        100.0
+    } else {
+        0.0
+    };
+    let now = chrono::Utc::now().timestamp();
+    json!({
+        "hash": hash,
+        "name": tt.name,
+        "state": state,
+        "progress": progress,
+        "eta": tt.eta,
+        "message": tt.error_string.unwrap_or_else(|| "OK".to_string()),
+        "is_finished": tt.is_finished,
+        "save_path": tt.download_dir,
+        "download_location": tt.download_dir,
+        "total_size": tt.total_size,
+        "total_done": total_done,
+        "time_added": now - tt.seconds_downloading.max(0),
+        "active_time": tt.seconds_downloading,
+        "seeding_time": 0,
+        "ratio": 0.0,
+        "is_auto_managed": false,
+        "stop_at_ratio": false,
+        "remove_at_ratio": false,
+        "stop_ratio": 0.0,
+        "label": label,
+    })
+}
@@ -1,29 +1,61 @@
 use crate::{
     // downloader::DownloadStatus,
+    download_system::transfer::Transfer,
     services::putio::{self, PutIOTransfer},
-    services::transmission::{TransmissionRequest, TransmissionTorrent, TransmissionTorrentStatus},
-    AppData, Config,
+    services::transmission::{
+        QueueMove, TorrentActionArguments, TorrentAdd, TorrentGetArguments, TorrentIds,
+        TorrentRemoveArguments, TorrentSetArguments, TorrentSource, TransmissionSessionStats,
+        TransmissionStats, TransmissionTorrent, TransmissionTorrentStatus,
+    },
+    state::OrphanFile,
+    AppData,
 };
 use actix_web::web;
-use anyhow::Result;
-use base64::Engine;
+use anyhow::{Context, Result};
 use colored::Colorize;
 use lava_torrent::torrent::v1::Torrent;
 use log::{error, info, warn};
 use magnet_url::Magnet;
 use serde_json::json;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
 use std::sync::Arc;
 
-fn determine_category(download_dir: &str, config: &Config) -> String {
-    let arrs = config.all_arrs();
+/// Works out the category of a torrent-add. Sonarr/Radarr send their
+/// category as a label, so a label naming a known category wins; other labels
+/// are ignored. Otherwise a `download-dir` path component equal to a
+/// configured *arr category is used; matching whole components keeps `tv`
+/// from matching `/downloads/tv-anime`.
+async fn determine_category(
+    download_dir: &str,
+    labels: &[String],
+    app_data: &web::Data<AppData>,
+) -> String {
+    let known = known_categories(app_data).await;
+    if let Some(label) = labels.iter().find(|l| known.contains_key(l.as_str())) {
+        info!("category match: using label {:?}", label);
+        return label.to_string();
+    }
+    if !labels.is_empty() {
+        info!(
+            "category check: no known category among labels {:?}",
+            labels
+        );
+    }
+
+    let arrs = app_data.config.all_arrs();
     if arrs.is_empty() {
         warn!("category check: no *arr instances configured");
     }
 
+    let in_download_dir = |c: &str| {
+        Path::new(download_dir)
+            .components()
+            .any(|p| p.as_os_str() == c)
+    };
     for (name, _kind, arr) in &arrs {
         match &arr.category {
-            Some(c) if download_dir.contains(c) => {
+            Some(c) if in_download_dir(c) => {
                 info!(
                     "category match: download_dir={:?} contains {} category {:?}",
                     download_dir, name, c
@@ -47,139 +79,191 @@ fn determine_category(download_dir: &str, config: &Config) -> String {
 
 pub(crate) async fn handle_torrent_add(
     api_token: &str,
-    payload: &web::Json<TransmissionRequest>,
+    add: &TorrentAdd,
     app_data: &web::Data<AppData>,
 ) -> Result<Option<serde_json::Value>> {
-    let arguments = payload.arguments.as_ref().unwrap().as_object().unwrap();
-
-    let raw_download_dir = arguments.get("download-dir").and_then(|v| v.as_str());
     info!(
-        "torrent-add: received download-dir={:?} (configured default: {:?})",
-        raw_download_dir, app_data.config.download_directory
+        "torrent-add: received download-dir={:?} labels={:?} (configured default: {:?})",
+        add.download_dir, add.labels, app_data.config.download_directory
     );
-    let download_dir = raw_download_dir
+    let download_dir = add
+        .download_dir
+        .as_deref()
         .unwrap_or(&app_data.config.download_directory)
         .to_string();
 
-    let category = determine_category(&download_dir, &app_data.config);
-    if arguments.contains_key("metainfo") {
-        // .torrent files
-        let b64 = arguments["metainfo"].as_str().unwrap();
-        let bytes = base64::engine::general_purpose::STANDARD
-            .decode(b64)
-            .unwrap();
-        putio::upload_file(api_token, &bytes).await?;
-
-        match Torrent::read_from_bytes(bytes) {
-            Ok(t) => {
-                let hash = t.info_hash();
-                let full_download_dir = if category != "default" {
-                    format!("{}/{}", app_data.config.download_directory, category)
-                } else {
-                    app_data.config.download_directory.clone()
-                };
-                info!(
-                    "torrent-add: storing state for hash={} category={} dir={}",
-                    hash.to_lowercase(), category, full_download_dir
-                );
-                app_data.state.add_transfer(
-                    hash.to_lowercase(),
-                    category.clone(),
-                    full_download_dir
-                ).await?;
-                info!(
-                    "{}: torrent uploaded (category: {})",
-                    format!("[ffff: {}]", t.name).magenta(),
-                    category
-                );
-            }
-            Err(_) => info!("New torrent uploaded (category: {})", category),
-        };
-    } else {
-        // Magnet links
-        let magnet_url = arguments["filename"].as_str().unwrap();
-        putio::add_transfer(api_token, magnet_url).await?;
-        match Magnet::new(magnet_url) {
-            Ok(m) => {
-                if let Some(xt) = &m.xt {
-                    let hash = xt.strip_prefix("urn:btih:").unwrap_or(xt);
-                    let full_download_dir = if category != "default" {
-                        format!("{}/{}", app_data.config.download_directory, category)
-                    } else {
-                        app_data.config.download_directory.clone()
-                    };
+    let category = determine_category(&download_dir, &add.labels, app_data).await;
+    add_torrent(api_token, &add.source, &category, &add.labels, app_data).await?;
+    Ok(None)
+}
+
+/// Directory a category downloads to: the save path of a category created
+/// through the qBittorrent API if it has one, otherwise a subdirectory of the
+/// download directory.
+pub(crate) async fn category_download_dir(category: &str, app_data: &web::Data<AppData>) -> String {
+    if category == "default" {
+        return app_data.config.download_directory.clone();
+    }
+    match app_data.state.categories().await.remove(category) {
+        Some(path) if !path.is_empty() => path,
+        _ => format!("{}/{}", app_data.config.download_directory, category),
+    }
+}
+
+/// Every known category: the ones configured for the *arrs plus the ones
+/// created by a client (qBittorrent categories, Deluge labels), mapped to
+/// their download directory.
+pub(crate) async fn known_categories(app_data: &web::Data<AppData>) -> BTreeMap<String, String> {
+    let mut out = BTreeMap::new();
+    for (_, _, arr) in app_data.config.all_arrs() {
+        if let Some(c) = &arr.category {
+            out.insert(c.clone(), category_download_dir(c, app_data).await);
+        }
+    }
+    for name in app_data.state.categories().await.into_keys() {
+        let path = category_download_dir(&name, app_data).await;
+        out.insert(name, path);
+    }
+    out
+}
+
+/// Adds a torrent to put.io and records the category/download-dir it should
+/// be downloaded to. Shared by every client protocol putioarr speaks. Returns
+/// the info hash, if it could be determined.
+pub(crate) async fn add_torrent(
+    api_token: &str,
+    source: &TorrentSource,
+    category: &str,
+    labels: &[String],
+    app_data: &web::Data<AppData>,
+) -> Result<Option<String>> {
+    let full_download_dir = category_download_dir(category, app_data).await;
+    let hash = match source {
+        TorrentSource::Metainfo(bytes) => {
+            // .torrent files
+            putio::upload_file(api_token, bytes).await?;
+
+            match Torrent::read_from_bytes(bytes) {
+                Ok(t) => {
+                    let hash = t.info_hash().to_lowercase();
                     info!(
-                        "torrent-add (magnet): storing state for hash={} category={} dir={}",
-                        hash.to_lowercase(), category, full_download_dir
+                        "torrent-add: storing state for hash={} category={} dir={}",
+                        hash, category, full_download_dir
                     );
-                    app_data.state.add_transfer(
-                        hash.to_lowercase(),
-                        category.clone(),
-                        full_download_dir
-                    ).await?;
-                } else {
-                    warn!(
-                        "torrent-add (magnet): no xt field in magnet url, cannot store category/dir state (category={})",
-                        category
-                    );
-                }
-                if let Some(dn) = m.dn {
+                    app_data
+                        .state
+                        .add_transfer(
+                            hash.clone(),
+                            category.to_string(),
+                            full_download_dir,
+                            labels.to_vec(),
+                        )
+                        .await?;
                     info!(
-                        "{}: magnet link uploaded (category: {})",
-                        format!("[ffff: {}]", urldecode::decode(dn)).magenta(),
+                        "{}: torrent uploaded (category: {})",
+                        format!("[ffff: {}]", t.name).magenta(),
                         category
                     );
-                } else {
-                    info!("magnet link uploaded (category: {})", category);
+                    Some(hash)
+                }
+                Err(_) => {
+                    info!("New torrent uploaded (category: {})", category);
+                    None
                 }
             }
-            _ => {
-                info!("unknown magnet link uploaded (category: {})", category);
+        }
+        TorrentSource::Url(magnet_url) => {
+            // Magnet links
+            putio::add_transfer(api_token, magnet_url).await?;
+            match Magnet::new(magnet_url) {
+                Ok(m) => {
+                    let hash =
+                        m.xt.as_ref()
+                            .map(|xt| xt.strip_prefix("urn:btih:").unwrap_or(xt).to_lowercase());
+                    if let Some(hash) = &hash {
+                        info!(
+                            "torrent-add (magnet): storing state for hash={} category={} dir={}",
+                            hash, category, full_download_dir
+                        );
+                        app_data
+                            .state
+                            .add_transfer(
+                                hash.clone(),
+                                category.to_string(),
+                                full_download_dir,
+                                labels.to_vec(),
+                            )
+                            .await?;
+                    } else {
+                        warn!(
+                            "torrent-add (magnet): no xt field in magnet url, cannot store category/dir state (category={})",
+                            category
+                        );
+                    }
+                    if let Some(dn) = m.dn {
+                        info!(
+                            "{}: magnet link uploaded (category: {})",
+                            format!("[ffff: {}]", urldecode::decode(dn)).magenta(),
+                            category
+                        );
+                    } else {
+                        info!("magnet link uploaded (category: {})", category);
+                    }
+                    hash
+                }
+                _ => {
+                    info!("unknown magnet link uploaded (category: {})", category);
+                    None
+                }
             }
         }
     };
-    Ok(None)
+    Ok(hash)
 }
 
 pub(crate) async fn handle_torrent_remove(
     api_token: &str,
-    payload: &web::Json<TransmissionRequest>,
-) -> Option<serde_json::Value> {
-    // TODO: leanup all the unwrap stuff
-    let arguments = payload.arguments.as_ref().unwrap().as_object().unwrap();
-    let ids: Vec<&str> = arguments
-        .get("ids")
-        .unwrap()
-        .as_array()
-        .unwrap()
-        .iter()
-        .map(|id| id.as_str().unwrap())
+    args: &TorrentRemoveArguments,
+    app_data: &web::Data<AppData>,
+) -> Result<Option<serde_json::Value>> {
+    remove_torrents(api_token, Some(&args.ids), args.delete_local_data, app_data).await?;
+    Ok(None)
+}
+
+/// Removes the put.io transfers matching `ids` (all of them when `None`),
+/// and their files when `delete_data` is set.
+pub(crate) async fn remove_torrents(
+    api_token: &str,
+    ids: Option<&TorrentIds>,
+    delete_data: bool,
+    app_data: &web::Data<AppData>,
+) -> Result<()> {
+    // Also hashes that never made it to put.io, e.g. stopped right after the
+    // add failed.
+    for hash in ids.map(TorrentIds::hashes).unwrap_or_default() {
+        app_data.state.forget_transfer(hash).await;
+        app_data.download_queue.clear_bandwidth_priority(hash);
+    }
+
+    let putio_transfers: Vec<PutIOTransfer> = putio::list_transfers(api_token)
+        .await
+        .context("failed to list put.io transfers for removal")?
+        .transfers
+        .into_iter()
+        .filter(|t| ids.is_none_or(|ids| ids.matches(t.id, t.hash.as_deref())))
         .collect();
-    let delete_local_data = arguments
-        .get("delete-local-data")
-        .unwrap()
-        .as_bool()
-        .unwrap();
-
-    let putio_transfers: Vec<PutIOTransfer> = match putio::list_transfers(api_token).await {
-        Ok(r) => r
-            .transfers
-            .into_iter()
-            .filter(|t| ids.contains(&t.hash.clone().unwrap_or(String::from("no_hash")).as_str()))
-            .collect(),
-        Err(e) => {
-            error!("Failed to list put.io transfers for removal: {}", e);
-            return None;
-        }
-    };
 
     for t in putio_transfers {
         if let Err(e) = putio::remove_transfer(api_token, t.id).await {
             error!("Failed to remove put.io transfer {}: {}", t.id, e);
             continue;
         }
+        if let Some(hash) = &t.hash {
+            app_data.state.forget_transfer(hash).await;
+            app_data.download_queue.clear_bandwidth_priority(hash);
+        }
 
-        if t.userfile_exists && delete_local_data {
+        if t.userfile_exists && delete_data {
             if let Some(file_id) = t.file_id {
                 if let Err(e) = putio::delete_file(api_token, file_id).await {
                     error!("Failed to delete put.io file {}: {}", file_id, e);
@@ -188,13 +272,198 @@ pub(crate) async fn handle_torrent_remove(
         }
     }
 
-    None
+    Ok(())
+}
+
+/// put.io transfers and orphaned watch-folder files matching `ids`.
+async fn select_transfers(
+    api_token: &str,
+    ids: Option<&TorrentIds>,
+    app_data: &web::Data<AppData>,
+) -> Result<(Vec<PutIOTransfer>, Vec<OrphanFile>)> {
+    let transfers = putio::list_transfers(api_token)
+        .await
+        .context("failed to list put.io transfers")?
+        .transfers
+        .into_iter()
+        .filter(|t| ids.is_none_or(|ids| ids.matches(t.id, t.hash.as_deref())))
+        .collect();
+    let orphans = app_data
+        .state
+        .orphans()
+        .await
+        .into_iter()
+        .filter(|o| {
+            ids.is_none_or(|ids| {
+                u64::try_from(o.file_id).is_ok_and(|id| ids.matches(id, Some(&o.hash)))
+            })
+        })
+        .collect();
+    Ok((transfers, orphans))
+}
+
+/// Applies the per-transfer download speed limit. The *arrs also send seeding
+/// limits here, which don't apply since put.io does the seeding.
+pub(crate) async fn handle_torrent_set(
+    api_token: &str,
+    args: &TorrentSetArguments,
+    app_data: &web::Data<AppData>,
+) -> Result<Option<serde_json::Value>> {
+    if args.download_limit.is_none()
+        && args.download_limited.is_none()
+        && args.bandwidth_priority.is_none()
+    {
+        return Ok(None);
+    }
+    let (transfers, orphans) = select_transfers(api_token, args.ids.as_ref(), app_data).await?;
+    let hashes = transfers
+        .into_iter()
+        .filter_map(|t| t.hash)
+        .chain(orphans.into_iter().map(|o| o.hash));
+    for hash in hashes {
+        info!(
+            "torrent-set: hash={} downloadLimit={:?} downloadLimited={:?} bandwidthPriority={:?}",
+            hash, args.download_limit, args.download_limited, args.bandwidth_priority
+        );
+        if args.download_limit.is_some() || args.download_limited.is_some() {
+            app_data
+                .state
+                .set_transfer_download_limit(&hash, args.download_limit, args.download_limited)
+                .await;
+        }
+        if let Some(priority) = args.bandwidth_priority {
+            app_data
+                .download_queue
+                .set_bandwidth_priority(&hash, priority);
+        }
+    }
+    Ok(None)
+}
+
+/// Moves transfers in the local download queue.
+pub(crate) async fn handle_queue_move(
+    api_token: &str,
+    to: QueueMove,
+    args: &TorrentActionArguments,
+    app_data: &web::Data<AppData>,
+) -> Result<Option<serde_json::Value>> {
+    let (transfers, orphans) = select_transfers(api_token, args.ids.as_ref(), app_data).await?;
+    let hashes = transfers
+        .into_iter()
+        .filter_map(|t| t.hash)
+        .chain(orphans.into_iter().map(|o| o.hash));
+    for hash in hashes {
+        info!("queue-move: hash={} to={:?}", hash, to);
+        app_data.download_queue.move_transfer(&hash, to);
+    }
+    Ok(None)
+}
+
+pub(crate) async fn handle_torrent_stop(
+    api_token: &str,
+    args: &TorrentActionArguments,
+    app_data: &web::Data<AppData>,
+) -> Result<Option<serde_json::Value>> {
+    let (transfers, orphans) = select_transfers(api_token, args.ids.as_ref(), app_data).await?;
+    let hashes = transfers
+        .into_iter()
+        .filter_map(|t| t.hash)
+        .chain(orphans.into_iter().map(|o| o.hash));
+    for hash in hashes {
+        info!("torrent-stop: stopping hash={}", hash);
+        app_data.state.stop_transfer(&hash).await;
+    }
+    Ok(None)
+}
+
+pub(crate) async fn handle_torrent_start(
+    api_token: &str,
+    args: &TorrentActionArguments,
+    app_data: &web::Data<AppData>,
+) -> Result<Option<serde_json::Value>> {
+    let (transfers, orphans) = select_transfers(api_token, args.ids.as_ref(), app_data).await?;
+    for t in transfers {
+        let Some(hash) = &t.hash else { continue };
+        if app_data.state.start_transfer(hash).await
+            && !app_data.state.is_local_complete(t.id).await
+        {
+            info!("torrent-start: resuming hash={}", hash);
+            app_data.state.request_requeue(t.id).await;
+        }
+    }
+    for o in orphans {
+        if app_data.state.start_transfer(&o.hash).await {
+            info!("torrent-start: resuming orphan {}", o.name);
+            // Dropping it from tracking lets the next watch-folder scan queue
+            // it again.
+            app_data.state.remove_orphan(o.file_id).await;
+        }
+    }
+    Ok(None)
+}
+
+pub(crate) async fn handle_torrent_verify(
+    api_token: &str,
+    args: &TorrentActionArguments,
+    app_data: &web::Data<AppData>,
+) -> Result<Option<serde_json::Value>> {
+    let (transfers, _) = select_transfers(api_token, args.ids.as_ref(), app_data).await?;
+    for t in transfers {
+        // Nothing local to verify until putioarr has finished pulling it.
+        if !t.is_downloadable() || !app_data.state.is_local_complete(t.id).await {
+            continue;
+        }
+        let transfer = Transfer::from(app_data.clone(), &t);
+        // Verifying reads the whole put.io file tree; don't hold up the RPC.
+        actix_rt::spawn(async move {
+            if let Err(e) = transfer.verify().await {
+                warn!("{}: verify failed: {}", transfer, e);
+            }
+        });
+    }
+    Ok(None)
 }
 
 pub(crate) async fn handle_torrent_get(
     api_token: &str,
+    args: &TorrentGetArguments,
     app_data: &web::Data<AppData>,
 ) -> Option<serde_json::Value> {
+    let transmission_transfers =
+        list_torrents(api_token, args.ids.as_ref(), args.wants("name"), app_data).await;
+
+    let torrents: Vec<serde_json::Value> = transmission_transfers
+        .iter()
+        .map(|tt| {
+            let mut value = json!(tt);
+            // Only return what the client asked for; a full torrent-get on a
+            // large account is a lot of JSON nobody reads.
+            if !args.fields.is_empty() {
+                if let Some(obj) = value.as_object_mut() {
+                    obj.retain(|k, _| args.fields.iter().any(|f| f == k));
+                }
+            }
+            value
+        })
+        .collect();
+
+    let mut arguments = serde_json::Map::new();
+    arguments.insert(String::from("torrents"), json!(torrents));
+
+    Some(json!(arguments))
+}
+
+/// Builds the Transmission view of every put.io transfer (and orphaned
+/// watch-folder file) matching `ids`. Resolving the real file/folder name costs
+/// a put.io `list_files` call per uncached transfer, so it's only done when
+/// `resolve_names` is set.
+pub(crate) async fn list_torrents(
+    api_token: &str,
+    ids: Option<&TorrentIds>,
+    resolve_names: bool,
+    app_data: &web::Data<AppData>,
+) -> Vec<TransmissionTorrent> {
+    let ids = ids.filter(|ids| !ids.is_recently_active());
     let transfers = match putio::list_transfers(api_token).await {
         Ok(r) => r.transfers,
         Err(e) => {
@@ -209,6 +478,10 @@ pub(crate) async fn handle_torrent_get(
     let active_file_ids: HashSet<i64> = transfers.iter().filter_map(|t| t.file_id).collect();
     app_data.state.retain_file_names(&active_file_ids).await;
 
+    let transfers = transfers
+        .into_iter()
+        .filter(|t| ids.is_none_or(|ids| ids.matches(t.id, t.hash.as_deref())));
+
     // Allocate the token once and share it cheaply (refcount bump) with each
     // per-transfer task, rather than allocating a new String per transfer.
     let api_token: Arc<str> = Arc::from(api_token);
@@ -217,21 +490,23 @@ pub(crate) async fn handle_torrent_get(
         let api_token = Arc::clone(&api_token);
         async move {
             let mut tt: TransmissionTorrent = t.clone().into();
-            // Get the correct download directory from state if available
-            if let Some(hash) = &t.hash {
-                tt.download_dir = app_data.state.get_download_dir_for_transfer(
-                    hash,
-                    &app_data.config.download_directory
-                ).await;
-            } else {
-                tt.download_dir = app_data.config.download_directory.clone();
+            // Get the correct download directory and labels from state if available
+            match t.hash.as_deref() {
+                Some(hash) => match app_data.state.get_transfer(hash).await {
+                    Some(state) => {
+                        tt.download_dir = state.download_dir;
+                        tt.labels = state.labels;
+                    }
+                    None => tt.download_dir = app_data.config.download_directory.clone(),
+                },
+                None => tt.download_dir = app_data.config.download_directory.clone(),
             }
             // put.io's transfer name often differs from the actual downloaded
             // file/folder name (e.g. an indexer prefix like "www.foo.org - "),
             // but the *arr locates the download at <download_dir>/<name>. Report
             // the real put.io file/folder name (the one we download into) so the
             // import doesn't fail with "No files found eligible for import" (#20).
-            if let Some(file_id) = t.file_id {
+            if let Some(file_id) = t.file_id.filter(|_| resolve_names) {
                 let resolved = match app_data.state.get_file_name(file_id).await {
                     Some(name) => Some(name),
                     // Don't re-hit the API for a lookup that recently failed
@@ -239,7 +514,10 @@ pub(crate) async fn handle_torrent_get(
                     None if app_data.state.name_lookup_suppressed(file_id).await => None,
                     None => match putio::list_files(&api_token, file_id).await {
                         Ok(r) => {
-                            app_data.state.set_file_name(file_id, r.parent.name.clone()).await;
+                            app_data
+                                .state
+                                .set_file_name(file_id, r.parent.name.clone())
+                                .await;
                             Some(r.parent.name)
                         }
                         Err(e) => {
@@ -269,6 +547,20 @@ pub(crate) async fn handle_torrent_get(
                 tt.left_until_done = std::cmp::max(tt.total_size, 1);
                 tt.status = TransmissionTorrentStatus::Downloading;
             }
+            if let Some(reason) = app_data.state.waiting_for_space(t.id).await {
+                tt.status = TransmissionTorrentStatus::Queued;
+                tt.error_string = Some(reason);
+            }
+            if let Some(hash) = &t.hash {
+                if app_data.state.is_stopped(hash).await {
+                    tt.status = TransmissionTorrentStatus::Stopped;
+                }
+                if let Some(limit) = app_data.state.transfer_download_limit(hash).await {
+                    tt.download_limit = limit.limit();
+                    tt.download_limited = limit.is_enabled();
+                }
+                tt.bandwidth_priority = app_data.download_queue.bandwidth_priority(hash);
+            }
             tt
         }
     });
@@ -285,7 +577,13 @@ pub(crate) async fn handle_torrent_get(
             Ok(id) => id,
             Err(_) => continue,
         };
+        if ids.is_some_and(|ids| !ids.matches(id, Some(&orphan.hash))) {
+            continue;
+        }
         let complete = app_data.state.is_local_complete(id).await;
+        let stopped = app_data.state.is_stopped(&orphan.hash).await;
+        let waiting_for_space = app_data.state.waiting_for_space(id).await;
+        let limit = app_data.state.transfer_download_limit(&orphan.hash).await;
         // Report consistent size/progress. Keep left_until_done <= total_size,
         // and when incomplete report a non-zero amount remaining even if the
         // size is unknown (put.io omitted it) so a client can't read 0/0 as
@@ -298,6 +596,7 @@ pub(crate) async fn handle_torrent_get(
         } else {
             (1, 1)
         };
+        let bandwidth_priority = app_data.download_queue.bandwidth_priority(&orphan.hash);
         transmission_transfers.push(TransmissionTorrent {
             id,
             hash_string: Some(orphan.hash),
@@ -309,24 +608,87 @@ pub(crate) async fn handle_torrent_get(
             eta: 0,
             status: if complete {
                 TransmissionTorrentStatus::Seeding
+            } else if stopped {
+                TransmissionTorrentStatus::Stopped
+            } else if waiting_for_space.is_some() {
+                TransmissionTorrentStatus::Queued
             } else {
                 TransmissionTorrentStatus::Downloading
             },
             seconds_downloading: 0,
-            error_string: None,
+            error_string: waiting_for_space,
             downloaded_ever: if complete { size } else { 0 },
             seed_ratio_limit: 0.0,
             seed_ratio_mode: 0,
             seed_idle_limit: 0,
             seed_idle_mode: 0,
             file_count: 1,
+            labels: Vec::new(),
+            download_limit: limit.as_ref().map_or(100, |l| l.limit()),
+            download_limited: limit.is_some_and(|l| l.is_enabled()),
+            bandwidth_priority,
         });
     }
 
-    let torrents = json!(transmission_transfers);
+    transmission_transfers
+}
 
-    let mut arguments = serde_json::Map::new();
-    arguments.insert(String::from("torrents"), torrents);
+pub(crate) async fn handle_session_stats(
+    api_token: &str,
+    app_data: &web::Data<AppData>,
+) -> Option<serde_json::Value> {
+    let transfers = match putio::list_transfers(api_token).await {
+        Ok(r) => r.transfers,
+        Err(e) => {
+            error!("Failed to list put.io transfers: {}", e);
+            Vec::new()
+        }
+    };
 
-    Some(json!(arguments))
+    let mut active = 0;
+    let mut paused = 0;
+    for t in &transfers {
+        if TransmissionTorrentStatus::from(t.status.clone()) == TransmissionTorrentStatus::Stopped {
+            paused += 1;
+        } else {
+            active += 1;
+        }
+    }
+    // Orphaned watch-folder files are reported as torrents too (issue #34);
+    // they're active until putioarr has pulled them home.
+    let orphans = app_data.state.orphans().await;
+    for orphan in &orphans {
+        match u64::try_from(orphan.file_id) {
+            Ok(id) if app_data.state.is_local_complete(id).await => {}
+            _ => active += 1,
+        }
+    }
+
+    // put.io seeds in the cloud, so its upload rate is the only upload there
+    // is. Downloads are what putioarr itself pulls to local disk.
+    let upload_speed = transfers
+        .iter()
+        .filter_map(|t| t.up_speed)
+        .map(|s| s.max(0) as u64)
+        .sum();
+    let local = app_data.state.download_stats();
+    // Counters live in memory only, so the cumulative stats cover this
+    // session just like the current ones.
+    let stats = TransmissionStats {
+        uploaded_bytes: 0,
+        downloaded_bytes: local.downloaded_bytes,
+        files_added: local.files_downloaded,
+        session_count: 1,
+        seconds_active: local.seconds_active,
+    };
+
+    Some(json!(TransmissionSessionStats {
+        active_torrent_count: active,
+        download_speed: local.download_speed,
+        paused_torrent_count: paused,
+        torrent_count: (transfers.len() + orphans.len()) as u64,
+        upload_speed,
+        cumulative_stats: stats,
+        current_stats: stats,
+    }))
 }
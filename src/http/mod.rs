@@ -1,2 +1,6 @@
+pub mod deluge;
 pub mod handlers;
+pub mod qbittorrent;
 pub mod routes;
+pub mod session;
+pub mod webhook;
@@ -0,0 +1,575 @@
+use crate::{
+    http::handlers::{
+        add_torrent, category_download_dir, known_categories, list_torrents, remove_torrents,
+    },
+    services::transmission::{
+        QueueMove, TorrentId, TorrentIds, TorrentSource, TransmissionTorrent,
+        TransmissionTorrentStatus,
+    },
+    AppData,
+};
+use actix_multipart::Multipart;
+use actix_web::{cookie::Cookie, get, post, web, Either, HttpRequest, HttpResponse};
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashSet};
+
+/// Version reported to clients. The *arrs only use it to pick API quirks.
+const QBITTORRENT_VERSION: &str = "v4.6.7";
+const WEBAPI_VERSION: &str = "2.9.3";
+
+/// Cookie carrying the login session.
+const SID_COOKIE: &str = "SID";
+
+/// Registers the subset of the qBittorrent v2 WebAPI the *arrs use. It's
+/// served on top of the same put.io calls and state as the Transmission RPC,
+/// except that categories are taken as-is instead of guessed from a directory.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(login)
+        .service(version)
+        .service(webapi_version)
+        .service(preferences)
+        .service(torrents_info)
+        .service(torrents_add)
+        .service(torrents_delete)
+        .service(categories)
+        .service(create_category)
+        .service(set_category)
+        .service(set_share_limits)
+        .service(top_prio)
+        .service(bottom_prio)
+        .service(increase_prio)
+        .service(decrease_prio);
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+    username: String,
+    password: String,
+}
+
+#[post("/api/v2/auth/login")]
+async fn login(form: web::Form<LoginForm>, app_data: web::Data<AppData>) -> HttpResponse {
+    if form.username != app_data.config.username || form.password != app_data.config.password {
+        return HttpResponse::Ok().body("Fails.");
+    }
+    HttpResponse::Ok()
+        .cookie(
+            Cookie::build(SID_COOKIE, app_data.qbittorrent_sid.current())
+                .path("/")
+                .http_only(true)
+                .finish(),
+        )
+        .body("Ok.")
+}
+
+fn authorized(req: &HttpRequest, app_data: &web::Data<AppData>) -> bool {
+    req.cookie(SID_COOKIE)
+        .is_some_and(|c| app_data.qbittorrent_sid.is_valid(c.value()))
+}
+
+fn forbidden() -> HttpResponse {
+    HttpResponse::Forbidden().body("Forbidden")
+}
+
+#[get("/api/v2/app/version")]
+async fn version(req: HttpRequest, app_data: web::Data<AppData>) -> HttpResponse {
+    if !authorized(&req, &app_data) {
+        return forbidden();
+    }
+    HttpResponse::Ok().body(QBITTORRENT_VERSION)
+}
+
+#[get("/api/v2/app/webapiVersion")]
+async fn webapi_version(req: HttpRequest, app_data: web::Data<AppData>) -> HttpResponse {
+    if !authorized(&req, &app_data) {
+        return forbidden();
+    }
+    HttpResponse::Ok().body(WEBAPI_VERSION)
+}
+
+#[get("/api/v2/app/preferences")]
+async fn preferences(req: HttpRequest, app_data: web::Data<AppData>) -> HttpResponse {
+    if !authorized(&req, &app_data) {
+        return forbidden();
+    }
+    // Seeding happens on put.io, so no local ratio/seeding limits apply.
+    // Queueing maps to putioarr's local download queue.
+    HttpResponse::Ok().json(json!({
+        "save_path": app_data.config.download_directory,
+        "temp_path_enabled": false,
+        "max_ratio_enabled": false,
+        "max_ratio": -1,
+        "max_ratio_act": 0,
+        "max_seeding_time_enabled": false,
+        "max_seeding_time": -1,
+        "max_inactive_seeding_time_enabled": false,
+        "max_inactive_seeding_time": -1,
+        "queueing_enabled": true,
+        "dht": true,
+    }))
+}
+
+#[derive(Serialize)]
+struct QbitTorrent {
+    hash: String,
+    name: String,
+    size: i64,
+    total_size: i64,
+    progress: f64,
+    downloaded: i64,
+    amount_left: i64,
+    dlspeed: i64,
+    upspeed: i64,
+    eta: i64,
+    state: &'static str,
+    category: String,
+    tags: String,
+    save_path: String,
+    content_path: String,
+    ratio: f64,
+    ratio_limit: f64,
+    seeding_time: i64,
+    seeding_time_limit: i64,
+    inactive_seeding_time_limit: i64,
+    added_on: i64,
+    completion_on: i64,
+    last_activity: i64,
+}
+
+impl QbitTorrent {
+    fn new(tt: TransmissionTorrent, hash: String, category: String) -> Self {
+        let now = chrono::Utc::now().timestamp();
+        let added_on = now - tt.seconds_downloading.max(0);
+        let progress = if tt.total_size > 0 {
+            (tt.total_size - tt.left_until_done) as f64 / tt.total_size as f64
+        } else if tt.is_finished {
+            1.0
+        } else {
+            0.0
+        };
+        let save_path = tt.download_dir.trim_end_matches('/').to_string();
+        Self {
+            content_path: format!("{}/{}", save_path, tt.name),
+            save_path,
+            state: qbit_state(&tt),
+            hash,
+            name: tt.name,
+            size: tt.total_size,
+            total_size: tt.total_size,
+            progress: progress.clamp(0.0, 1.0),
+            downloaded: tt.total_size - tt.left_until_done,
+            amount_left: tt.left_until_done,
+            dlspeed: 0,
+            upspeed: 0,
+            eta: tt.eta,
+            category,
+            tags: String::new(),
+            ratio: 0.0,
+            // -2: use the (disabled) global limits.
+            ratio_limit: -2.0,
+            seeding_time: 0,
+            seeding_time_limit: -2,
+            inactive_seeding_time_limit: -2,
+            added_on,
+            completion_on: if tt.is_finished { now } else { 0 },
+            last_activity: now,
+        }
+    }
+}
+
+/// Maps a torrent's Transmission status to the closest qBittorrent state.
+fn qbit_state(tt: &TransmissionTorrent) -> &'static str {
+    if tt.error_string.as_deref().is_some_and(|e| !e.is_empty()) {
+        return "error";
+    }
+    match tt.status {
+        TransmissionTorrentStatus::Stopped if tt.is_finished => "pausedUP",
+        TransmissionTorrentStatus::Stopped => "pausedDL",
+        TransmissionTorrentStatus::CheckWait | TransmissionTorrentStatus::Check => "checkingDL",
+        TransmissionTorrentStatus::Queued => "queuedDL",
+        TransmissionTorrentStatus::Downloading => "downloading",
+        TransmissionTorrentStatus::SeedingWait | TransmissionTorrentStatus::Seeding => "uploading",
+    }
+}
+
+/// qBittorrent has no "default" category; that's an empty one.
+fn qbit_category(category: &str) -> String {
+    if category == "default" {
+        String::new()
+    } else {
+        category.to_string()
+    }
+}
+
+/// Parses qBittorrent's `|`-separated hash list; "all" selects everything.
+fn parse_hashes(hashes: &str) -> Option<TorrentIds> {
+    if hashes == "all" {
+        return None;
+    }
+    Some(TorrentIds::List(
+        hashes
+            .split('|')
+            .filter(|h| !h.is_empty())
+            .map(|h| TorrentId::Hash(h.to_string()))
+            .collect(),
+    ))
+}
+
+#[derive(Deserialize)]
+struct InfoQuery {
+    category: Option<String>,
+    hashes: Option<String>,
+}
+
+#[get("/api/v2/torrents/info")]
+async fn torrents_info(
+    req: HttpRequest,
+    query: web::Query<InfoQuery>,
+    app_data: web::Data<AppData>,
+) -> HttpResponse {
+    if !authorized(&req, &app_data) {
+        return forbidden();
+    }
+    let ids = query.hashes.as_deref().and_then(parse_hashes);
+    let torrents = list_torrents(
+        &app_data.config.putio.api_key,
+        ids.as_ref(),
+        true,
+        &app_data,
+    )
+    .await;
+
+    let mut out = Vec::new();
+    for tt in torrents {
+        let Some(hash) = tt.hash_string.clone() else {
+            continue;
+        };
+        let category = app_data
+            .state
+            .get_transfer(&hash)
+            .await
+            .map(|s| qbit_category(&s.source_category))
+            .unwrap_or_default();
+        if query.category.as_ref().is_some_and(|c| *c != category) {
+            continue;
+        }
+        out.push(QbitTorrent::new(tt, hash.to_lowercase(), category));
+    }
+    HttpResponse::Ok().json(out)
+}
+
+#[derive(Deserialize, Default)]
+struct AddForm {
+    urls: Option<String>,
+    category: Option<String>,
+    paused: Option<String>,
+}
+
+/// `torrents/add` is multipart when .torrent files are attached and a plain
+/// form when only urls are sent; accept both.
+#[post("/api/v2/torrents/add")]
+async fn torrents_add(
+    req: HttpRequest,
+    payload: Either<web::Form<AddForm>, Multipart>,
+    app_data: web::Data<AppData>,
+) -> HttpResponse {
+    if !authorized(&req, &app_data) {
+        return forbidden();
+    }
+    let (form, files) = match payload {
+        Either::Left(form) => (form.into_inner(), Vec::new()),
+        Either::Right(multipart) => match read_add_multipart(multipart).await {
+            Ok(r) => r,
+            Err(e) => {
+                error!("torrents/add: {:#}", e);
+                return HttpResponse::BadRequest().body(e.to_string());
+            }
+        },
+    };
+
+    let category = match form.category.as_deref() {
+        Some(c) if !c.is_empty() => c.to_string(),
+        _ => "default".to_string(),
+    };
+    let paused = form.paused.as_deref() == Some("true");
+    let sources = form
+        .urls
+        .iter()
+        .flat_map(|urls| urls.lines())
+        .map(str::trim)
+        .filter(|u| !u.is_empty())
+        .map(|u| TorrentSource::Url(u.to_string()))
+        .chain(files.into_iter().map(TorrentSource::Metainfo))
+        .collect::<Vec<_>>();
+    if sources.is_empty() {
+        return HttpResponse::Ok().body("Fails.");
+    }
+
+    let mut added = 0;
+    for source in &sources {
+        match add_torrent(
+            &app_data.config.putio.api_key,
+            source,
+            &category,
+            &[],
+            &app_data,
+        )
+        .await
+        {
+            Ok(hash) => {
+                added += 1;
+                if let (true, Some(hash)) = (paused, hash) {
+                    app_data.state.stop_transfer(&hash).await;
+                }
+            }
+            Err(e) => error!("torrents/add: {:#}", e),
+        }
+    }
+    if added == 0 {
+        HttpResponse::Ok().body("Fails.")
+    } else {
+        HttpResponse::Ok().body("Ok.")
+    }
+}
+
+async fn read_add_multipart(mut multipart: Multipart) -> Result<(AddForm, Vec<Vec<u8>>)> {
+    let mut form = AddForm::default();
+    let mut files = Vec::new();
+    while let Some(field) = multipart.next().await {
+        let mut field = field.map_err(|e| anyhow!("{}", e))?;
+        let name = field.name().unwrap_or_default().to_string();
+        let mut data = Vec::new();
+        while let Some(chunk) = field.next().await {
+            data.extend_from_slice(&chunk.map_err(|e| anyhow!("{}", e))?);
+        }
+        match name.as_str() {
+            "torrents" => files.push(data),
+            "urls" => form.urls = Some(String::from_utf8_lossy(&data).into_owned()),
+            "category" => form.category = Some(String::from_utf8_lossy(&data).into_owned()),
+            "paused" => form.paused = Some(String::from_utf8_lossy(&data).into_owned()),
+            _ => {}
+        }
+    }
+    Ok((form, files))
+}
+
+#[derive(Deserialize)]
+struct DeleteForm {
+    hashes: String,
+    #[serde(default, rename = "deleteFiles")]
+    delete_files: bool,
+}
+
+#[post("/api/v2/torrents/delete")]
+async fn torrents_delete(
+    req: HttpRequest,
+    form: web::Form<DeleteForm>,
+    app_data: web::Data<AppData>,
+) -> HttpResponse {
+    if !authorized(&req, &app_data) {
+        return forbidden();
+    }
+    let ids = parse_hashes(&form.hashes);
+    match remove_torrents(
+        &app_data.config.putio.api_key,
+        ids.as_ref(),
+        form.delete_files,
+        &app_data,
+    )
+    .await
+    {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(e) => {
+            error!("torrents/delete: {:#}", e);
+            HttpResponse::InternalServerError().body(e.to_string())
+        }
+    }
+}
+
+#[get("/api/v2/torrents/categories")]
+async fn categories(req: HttpRequest, app_data: web::Data<AppData>) -> HttpResponse {
+    if !authorized(&req, &app_data) {
+        return forbidden();
+    }
+    let categories: BTreeMap<String, serde_json::Value> = known_categories(&app_data)
+        .await
+        .into_iter()
+        .map(|(name, path)| (name.clone(), json!({ "name": name, "savePath": path })))
+        .collect();
+    HttpResponse::Ok().json(categories)
+}
+
+#[derive(Deserialize)]
+struct CreateCategoryForm {
+    category: String,
+    #[serde(default, rename = "savePath")]
+    save_path: String,
+}
+
+#[post("/api/v2/torrents/createCategory")]
+async fn create_category(
+    req: HttpRequest,
+    form: web::Form<CreateCategoryForm>,
+    app_data: web::Data<AppData>,
+) -> HttpResponse {
+    if !authorized(&req, &app_data) {
+        return forbidden();
+    }
+    let name = form.category.trim();
+    if name.is_empty() || name == "default" {
+        return HttpResponse::BadRequest().body("Invalid category name");
+    }
+    if known_categories(&app_data).await.contains_key(name) {
+        return HttpResponse::Conflict().body("Unable to create category");
+    }
+    info!("createCategory: {} (save path {:?})", name, form.save_path);
+    match app_data
+        .state
+        .add_category(name.to_string(), form.save_path.clone())
+        .await
+    {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[derive(Deserialize)]
+struct SetCategoryForm {
+    hashes: String,
+    #[serde(default)]
+    category: String,
+}
+
+#[post("/api/v2/torrents/setCategory")]
+async fn set_category(
+    req: HttpRequest,
+    form: web::Form<SetCategoryForm>,
+    app_data: web::Data<AppData>,
+) -> HttpResponse {
+    if !authorized(&req, &app_data) {
+        return forbidden();
+    }
+    let category = if form.category.is_empty() {
+        "default".to_string()
+    } else if known_categories(&app_data)
+        .await
+        .contains_key(&form.category)
+    {
+        form.category.clone()
+    } else {
+        return HttpResponse::Conflict().body("Incorrect category name");
+    };
+
+    let hashes: HashSet<String> = match parse_hashes(&form.hashes) {
+        Some(TorrentIds::List(ids)) => ids
+            .into_iter()
+            .filter_map(|id| match id {
+                TorrentId::Hash(h) => Some(h.to_lowercase()),
+                TorrentId::Id(_) => None,
+            })
+            .collect(),
+        _ => list_torrents(&app_data.config.putio.api_key, None, false, &app_data)
+            .await
+            .into_iter()
+            .filter_map(|t| t.hash_string.map(|h| h.to_lowercase()))
+            .collect(),
+    };
+    let download_dir = category_download_dir(&category, &app_data).await;
+    for hash in hashes {
+        info!("setCategory: hash={} category={}", hash, category);
+        if let Err(e) = app_data
+            .state
+            .add_transfer(hash, category.clone(), download_dir.clone(), Vec::new())
+            .await
+        {
+            error!("setCategory: {:#}", e);
+        }
+    }
+    HttpResponse::Ok().finish()
+}
+
+/// Seeding limits don't apply (put.io seeds), but the *arrs set them after
+/// every add and log a warning when that fails.
+#[post("/api/v2/torrents/setShareLimits")]
+async fn set_share_limits(req: HttpRequest, app_data: web::Data<AppData>) -> HttpResponse {
+    if !authorized(&req, &app_data) {
+        return forbidden();
+    }
+    HttpResponse::Ok().finish()
+}
+
+#[derive(Deserialize)]
+struct HashesForm {
+    hashes: String,
+}
+
+#[post("/api/v2/torrents/topPrio")]
+async fn top_prio(
+    req: HttpRequest,
+    form: web::Form<HashesForm>,
+    app_data: web::Data<AppData>,
+) -> HttpResponse {
+    queue_move(&req, &form.hashes, QueueMove::Top, &app_data).await
+}
+
+#[post("/api/v2/torrents/bottomPrio")]
+async fn bottom_prio(
+    req: HttpRequest,
+    form: web::Form<HashesForm>,
+    app_data: web::Data<AppData>,
+) -> HttpResponse {
+    queue_move(&req, &form.hashes, QueueMove::Bottom, &app_data).await
+}
+
+#[post("/api/v2/torrents/increasePrio")]
+async fn increase_prio(
+    req: HttpRequest,
+    form: web::Form<HashesForm>,
+    app_data: web::Data<AppData>,
+) -> HttpResponse {
+    queue_move(&req, &form.hashes, QueueMove::Up, &app_data).await
+}
+
+#[post("/api/v2/torrents/decreasePrio")]
+async fn decrease_prio(
+    req: HttpRequest,
+    form: web::Form<HashesForm>,
+    app_data: web::Data<AppData>,
+) -> HttpResponse {
+    queue_move(&req, &form.hashes, QueueMove::Down, &app_data).await
+}
+
+/// Moves torrents in the local download queue.
+async fn queue_move(
+    req: &HttpRequest,
+    hashes: &str,
+    to: QueueMove,
+    app_data: &web::Data<AppData>,
+) -> HttpResponse {
+    if !authorized(req, app_data) {
+        return forbidden();
+    }
+    let hashes: Vec<String> = match parse_hashes(hashes) {
+        Some(TorrentIds::List(ids)) => ids
+            .into_iter()
+            .filter_map(|id| match id {
+                TorrentId::Hash(h) => Some(h),
+                TorrentId::Id(_) => None,
+            })
+            .collect(),
+        _ => list_torrents(&app_data.config.putio.api_key, None, false, app_data)
+            .await
+            .into_iter()
+            .filter_map(|t| t.hash_string)
+            .collect(),
+    };
+    for hash in hashes {
+        info!("queue move: hash={} to={:?}", hash, to);
+        app_data.download_queue.move_transfer(&hash, to);
+    }
+    HttpResponse::Ok().finish()
+}
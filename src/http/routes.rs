@@ -1,7 +1,15 @@
 use crate::{
-    http::handlers::{handle_torrent_add, handle_torrent_get, handle_torrent_remove},
-    services::transmission::{TransmissionConfig, TransmissionRequest, TransmissionResponse},
-    AppData,
+    http::handlers::{
+        handle_queue_move, handle_session_stats, handle_torrent_add, handle_torrent_get,
+        handle_torrent_remove, handle_torrent_set, handle_torrent_start, handle_torrent_stop,
+        handle_torrent_verify,
+    },
+    http::session::SESSION_ID_HEADER,
+    services::transmission::{
+        TransmissionCall, TransmissionConfig, TransmissionFreeSpace, TransmissionRequest,
+        TransmissionResponse,
+    },
+    utils, AppData,
 };
 use actix_web::{
     get,
@@ -9,50 +17,142 @@ use actix_web::{
     post, web, HttpRequest, HttpResponse,
 };
 use actix_web_httpauth::headers::authorization::{Authorization, Basic};
-use anyhow::{bail, Context, Result};
-use log::error;
+use anyhow::{anyhow, bail, Context, Result};
+use log::{error, info, warn};
 use serde_json::json;
 
-const SESSION_ID: &str = "useless-session-id";
-
 #[post("/transmission/rpc")]
 pub(crate) async fn rpc_post(
-    payload: web::Json<TransmissionRequest>,
+    body: web::Bytes,
     req: HttpRequest,
     app_data: web::Data<AppData>,
 ) -> HttpResponse {
     let putio_api_token = &app_data.config.putio.api_key;
 
-    // Not sure if necessary since we might just look at the session id.
-    if validate_user(req, &app_data).await.is_err() {
-        return HttpResponse::Conflict()
-            .content_type(ContentType::json())
-            .insert_header(("X-Transmission-Session-Id", SESSION_ID))
-            .body("");
+    if validate_user(&req, &app_data).await.is_err() {
+        return unauthorized();
+    }
+
+    // Like Transmission, only serve requests carrying the current session id
+    // and answer anything else with a 409 telling the client which one to use.
+    let session_id = req
+        .headers()
+        .get(SESSION_ID_HEADER)
+        .and_then(|v| v.to_str().ok());
+    if !session_id.is_some_and(|id| app_data.session_id.is_valid(id)) {
+        return session_conflict(&app_data);
+    }
+
+    // Parse the body ourselves rather than through `web::Json`, so a malformed
+    // request gets a Transmission error response instead of a bare 400.
+    let payload: TransmissionRequest = match serde_json::from_slice(&body) {
+        Ok(p) => p,
+        Err(e) => return rpc_response(Err(anyhow!("malformed request: {}", e)), None),
+    };
+
+    let result = match payload.parse() {
+        Ok(call) => dispatch(call, putio_api_token, &app_data).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = &result {
+        error!("{}: {:#}", payload.method, e);
     }
+    rpc_response(result, payload.tag)
+}
 
-    let arguments = match payload.method.as_str() {
-        "session-get" => Some(json!(TransmissionConfig {
-            download_dir: app_data.config.download_directory.clone(),
-            ..Default::default()
-        })),
-        "torrent-get" => handle_torrent_get(putio_api_token, &app_data).await,
-        "torrent-set" => None, // Nothing to do here
-        "queue-move-top" => None,
-        "torrent-remove" => handle_torrent_remove(putio_api_token, &payload).await,
-        "torrent-add" => match handle_torrent_add(putio_api_token, &payload, &app_data).await {
-            Ok(v) => v,
-            Err(e) => {
-                error!("{}", e);
-                return HttpResponse::BadRequest().body(e.to_string());
+async fn dispatch(
+    call: TransmissionCall,
+    putio_api_token: &str,
+    app_data: &web::Data<AppData>,
+) -> Result<Option<serde_json::Value>> {
+    let arguments = match call {
+        TransmissionCall::SessionGet => {
+            let download_dir = &app_data.config.download_directory;
+            let free_space = match utils::disk_space(download_dir) {
+                Ok(space) => space.available as i64,
+                Err(e) => {
+                    warn!("session-get: {:#}", e);
+                    -1
+                }
+            };
+            let download_limit = app_data.state.download_limit();
+            Some(json!(TransmissionConfig {
+                download_dir: download_dir.clone(),
+                download_dir_free_space: free_space,
+                speed_limit_down: download_limit.limit(),
+                speed_limit_down_enabled: download_limit.is_enabled(),
+                ..Default::default()
+            }))
+        }
+        TransmissionCall::SessionSet(args) => {
+            let download_limit = app_data.state.download_limit();
+            if let Some(limit) = args.speed_limit_down {
+                info!("session-set: speed-limit-down={} kB/s", limit);
+                download_limit.set_limit(limit);
             }
-        },
-        _ => panic!("Unknwon method {}", payload.method),
+            if let Some(enabled) = args.speed_limit_down_enabled {
+                info!("session-set: speed-limit-down-enabled={}", enabled);
+                download_limit.set_enabled(enabled);
+            }
+            None
+        }
+        TransmissionCall::FreeSpace(args) => {
+            let path = args
+                .path
+                .unwrap_or_else(|| app_data.config.download_directory.clone());
+            let space = utils::disk_space(&path)?;
+            Some(json!(TransmissionFreeSpace {
+                path,
+                size_bytes: space.available,
+                total_size: space.total,
+            }))
+        }
+        TransmissionCall::SessionStats => handle_session_stats(putio_api_token, app_data).await,
+        TransmissionCall::TorrentGet(args) => {
+            handle_torrent_get(putio_api_token, &args, app_data).await
+        }
+        TransmissionCall::TorrentSet(args) => {
+            handle_torrent_set(putio_api_token, &args, app_data).await?
+        }
+        TransmissionCall::QueueMove(to, args) => {
+            handle_queue_move(putio_api_token, to, &args, app_data).await?
+        }
+        TransmissionCall::TorrentStart(args) => {
+            handle_torrent_start(putio_api_token, &args, app_data).await?
+        }
+        TransmissionCall::TorrentStop(args) => {
+            handle_torrent_stop(putio_api_token, &args, app_data).await?
+        }
+        TransmissionCall::TorrentVerify(args) => {
+            handle_torrent_verify(putio_api_token, &args, app_data).await?
+        }
+        TransmissionCall::TorrentRemove(args) => {
+            handle_torrent_remove(putio_api_token, &args, app_data).await?
+        }
+        TransmissionCall::TorrentAdd(add) => {
+            handle_torrent_add(putio_api_token, &add, app_data).await?
+        }
     };
+    Ok(arguments)
+}
 
-    let response = TransmissionResponse {
-        result: String::from("success"),
-        arguments,
+/// Builds the RPC response. Like Transmission, failures are reported with a
+/// 200 and a descriptive `result` rather than an HTTP error status.
+fn rpc_response(
+    result: Result<Option<serde_json::Value>>,
+    tag: Option<serde_json::Value>,
+) -> HttpResponse {
+    let response = match result {
+        Ok(arguments) => TransmissionResponse {
+            result: String::from("success"),
+            arguments,
+            tag,
+        },
+        Err(e) => TransmissionResponse {
+            result: format!("{:#}", e),
+            arguments: Some(json!({})),
+            tag,
+        },
     };
 
     HttpResponse::Ok()
@@ -63,18 +163,29 @@ pub(crate) async fn rpc_post(
 /// Pretty much only used for authentication.
 #[get("/transmission/rpc")]
 async fn rpc_get(req: HttpRequest, app_data: web::Data<AppData>) -> HttpResponse {
-    if validate_user(req, &app_data).await.is_err() {
-        return HttpResponse::Forbidden().body("forbidden");
+    if validate_user(&req, &app_data).await.is_err() {
+        return unauthorized();
     }
 
+    session_conflict(&app_data)
+}
+
+/// 409 carrying the session id the client has to use from now on.
+fn session_conflict(app_data: &web::Data<AppData>) -> HttpResponse {
     HttpResponse::Conflict()
         .content_type(ContentType::json())
-        .insert_header(("X-Transmission-Session-Id", SESSION_ID))
+        .insert_header((SESSION_ID_HEADER, app_data.session_id.current()))
         .body("")
-    // HttpResponse::Ok().body("Hello world!")
 }
-async fn validate_user(req: HttpRequest, app_data: &web::Data<AppData>) -> Result<()> {
-    let auth = Authorization::<Basic>::parse(&req)?;
+
+fn unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .insert_header(("WWW-Authenticate", "Basic realm=\"Transmission\""))
+        .body("Unauthorized")
+}
+
+pub(crate) async fn validate_user(req: &HttpRequest, app_data: &web::Data<AppData>) -> Result<()> {
+    let auth = Authorization::<Basic>::parse(req)?;
     let user_username = auth.as_ref().user_id();
     let user_password = auth.as_ref().password().context("No password given")?;
     if user_username == app_data.config.username && user_password == app_data.config.password {
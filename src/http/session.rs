@@ -0,0 +1,58 @@
+use rand::{distributions::Alphanumeric, Rng};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+/// Header Transmission uses to carry its CSRF token.
+pub const SESSION_ID_HEADER: &str = "X-Transmission-Session-Id";
+
+/// Transmission's CSRF protection token.
+///
+/// A client's first POST is answered with a 409 carrying the current id, and
+/// only requests presenting that id are served. The id is random per process
+/// and is rotated once it reaches its configured lifetime, after which clients
+/// holding the old one get another 409 and retry with the new one, exactly
+/// like against real Transmission.
+pub struct SessionId {
+    current: RwLock<(String, Instant)>,
+    /// Rotate the id once it's this old. `None` keeps it for the lifetime of
+    /// the process.
+    lifetime: Option<Duration>,
+}
+
+impl SessionId {
+    pub fn new(lifetime: Option<Duration>) -> Self {
+        Self {
+            current: RwLock::new((generate(), Instant::now())),
+            lifetime,
+        }
+    }
+
+    /// Returns the id clients should use, rotating it first if it expired.
+    pub fn current(&self) -> String {
+        {
+            let (id, created) = &*self.current.read().unwrap();
+            if self.lifetime.is_none_or(|l| created.elapsed() < l) {
+                return id.clone();
+            }
+        }
+        let mut current = self.current.write().unwrap();
+        // Another request may have rotated it while we waited for the lock.
+        if self.lifetime.is_some_and(|l| current.1.elapsed() >= l) {
+            *current = (generate(), Instant::now());
+        }
+        current.0.clone()
+    }
+
+    /// True if `candidate` is the current id.
+    pub fn is_valid(&self, candidate: &str) -> bool {
+        candidate == self.current()
+    }
+}
+
+fn generate() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(48)
+        .map(char::from)
+        .collect()
+}
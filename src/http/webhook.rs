@@ -0,0 +1,73 @@
+use crate::{http::routes::validate_user, AppData};
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use log::{debug, info};
+use serde_json::Value;
+use std::path::Path;
+
+/// Receives the "On Import"/"On Upgrade" notifications of a Sonarr, Radarr,
+/// Lidarr or Readarr webhook connection (Settings -> Connect -> Webhook, with the
+/// putioarr username and password). An import is matched to its transfer by
+/// `downloadId` and picked up by the import watcher right away, instead of on
+/// its next history poll.
+#[post("/webhook")]
+pub(crate) async fn webhook(
+    body: web::Json<Value>,
+    req: HttpRequest,
+    app_data: web::Data<AppData>,
+) -> HttpResponse {
+    if validate_user(&req, &app_data).await.is_err() {
+        return HttpResponse::Unauthorized()
+            .insert_header(("WWW-Authenticate", "Basic realm=\"putioarr\""))
+            .body("Unauthorized");
+    }
+
+    let event = body["eventType"].as_str().unwrap_or_default();
+    // Imports and upgrades are both "Download" events; everything else,
+    // including the *arr's "Test", is acknowledged and ignored.
+    if event != "Download" {
+        debug!("webhook: ignoring {} event", event);
+        return HttpResponse::Ok().finish();
+    }
+    let Some(hash) = body["downloadId"].as_str() else {
+        debug!("webhook: import without a downloadId, ignoring");
+        return HttpResponse::Ok().finish();
+    };
+
+    // Only imports a watcher is waiting for are kept; anything else (e.g. a
+    // download of another download client) would only pile up.
+    if !app_data.queue.awaits_import(hash) {
+        debug!("webhook: {} isn't waiting for an import, ignoring", hash);
+        return HttpResponse::Ok().finish();
+    }
+
+    let files = source_files(&body);
+    info!("webhook: {} imported {} file(s)", hash, files.len());
+    let category = app_data
+        .state
+        .get_transfer(hash)
+        .await
+        .map(|t| t.source_category);
+    app_data
+        .state
+        .record_webhook_import(hash, category, files)
+        .await;
+    HttpResponse::Ok().finish()
+}
+
+/// Names of the downloaded files the *arr imported, from `sourcePath` of
+/// the Sonarr (`episodeFile`/`episodeFiles`), Radarr (`movieFile`), Lidarr
+/// (`trackFiles`) or Readarr (`bookFiles`) payload.
+fn source_files(body: &Value) -> Vec<String> {
+    let single = ["episodeFile", "movieFile"].iter().map(|key| &body[key]);
+    let many = ["episodeFiles", "trackFiles", "bookFiles"]
+        .iter()
+        .filter_map(|key| body[key].as_array())
+        .flatten();
+    single
+        .chain(many)
+        // Readarr's book files only carry the imported `path`.
+        .filter_map(|file| file["sourcePath"].as_str().or(file["path"].as_str()))
+        .filter_map(|path| Path::new(path).file_name())
+        .map(|name| name.to_string_lossy().to_string())
+        .collect()
+}
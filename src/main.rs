@@ -1,4 +1,7 @@
-use crate::{http::routes, services::putio};
+use crate::{
+    http::{deluge, qbittorrent, routes, webhook},
+    services::putio,
+};
 use actix_web::{web, App, HttpServer};
 use anyhow::{bail, Context, Result};
 use clap::{Parser, Subcommand};
@@ -10,7 +13,7 @@ use figment::{
 };
 use log::{error, info};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 use utils::{generate_config, get_token};
 
 mod download_system;
@@ -49,6 +52,31 @@ fn default_import_timeout_secs() -> u64 {
     7200
 }
 
+/// Default for [`Config::download_segments`]: one stream per file.
+fn default_download_segments() -> u64 {
+    1
+}
+
+/// Default for [`Config::min_segment_size_mb`].
+fn default_min_segment_size_mb() -> u64 {
+    64
+}
+
+/// Default for [`Config::free_space_reserve_mb`] (1 GiB).
+fn default_free_space_reserve_mb() -> u64 {
+    1024
+}
+
+/// Default for [`Config::hook_timeout_secs`] (5 min).
+fn default_hook_timeout_secs() -> u64 {
+    300
+}
+
+/// Default for [`Config::history_lookback_records`].
+fn default_history_lookback_records() -> usize {
+    10000
+}
+
 /// Default for [`Config::watch_folder_interval_secs`] (60s), enforced at the
 /// type level so the documented default holds even without the Figment default layer.
 fn default_watch_folder_interval_secs() -> u64 {
@@ -59,7 +87,47 @@ fn default_watch_folder_interval_secs() -> u64 {
 pub struct Config {
     bind_address: String,
     download_directory: String,
+    /// Where transfers are assembled while downloading. Each one is moved to
+    /// its category directory once all of its files are in, so the *arr never
+    /// sees a half-complete download. Default: download in place.
+    #[serde(default)]
+    incomplete_directory: Option<String>,
+    /// Also download zip and tar archives, and unpack them before the
+    /// transfer is reported as downloaded. Default false.
+    #[serde(default)]
+    extract_archives: bool,
     download_workers: usize,
+    /// Fetch large files over this many concurrent connections, each pulling
+    /// its own byte range into a preallocated `.downloading` file. Default 1
+    /// (a single stream per file).
+    #[serde(default = "default_download_segments")]
+    download_segments: u64,
+    /// Smallest byte range (MiB) worth its own connection. Files smaller than
+    /// twice this are downloaded over a single stream. Default 64.
+    #[serde(default = "default_min_segment_size_mb")]
+    min_segment_size_mb: u64,
+    /// Space (MiB) to keep free on the download filesystem. A transfer that
+    /// doesn't fit next to this reserve waits until enough space frees up.
+    /// Default 1024.
+    #[serde(default = "default_free_space_reserve_mb")]
+    free_space_reserve_mb: u64,
+    /// Download speed limit in kB/s across all download workers, like
+    /// Transmission's `speed-limit-down`. Only enforced when
+    /// `speed_limit_down_enabled` is set; both can be changed at runtime
+    /// through `session-set`.
+    #[serde(default)]
+    speed_limit_down: u64,
+    #[serde(default)]
+    speed_limit_down_enabled: bool,
+    /// Time windows during which downloads are slowed down or paused,
+    /// configured as `[[schedule]]` entries. Empty (default) disables this.
+    #[serde(default)]
+    schedule: Vec<download_system::schedule::ScheduleWindow>,
+    /// Queue new transfers ahead of larger ones that are still waiting, so a
+    /// single episode doesn't wait for a season pack. Default false (first
+    /// come, first served).
+    #[serde(default)]
+    smallest_first: bool,
     loglevel: String,
     orchestration_workers: usize,
     password: String,
@@ -71,6 +139,26 @@ pub struct Config {
     /// disables the bound (watch indefinitely).
     #[serde(default = "default_import_timeout_secs")]
     import_timeout_secs: u64,
+    /// Shell command run after a transfer is downloaded and again after it's
+    /// imported, with `PUTIOARR_*` environment variables describing it. An
+    /// *arr's own `hook_command` takes precedence for its category.
+    #[serde(default)]
+    hook_command: Option<String>,
+    /// Seconds a hook command may run before it's killed. Default 300.
+    #[serde(default = "default_hook_timeout_secs")]
+    hook_timeout_secs: u64,
+    /// Don't report a transfer as downloaded to the *arr unless the hook
+    /// command succeeded. Default false.
+    #[serde(default)]
+    hook_blocks_completion: bool,
+    /// How many of the newest records of each *arr's history are read at
+    /// startup to find past imports. 0 reads all of it. Default 10000.
+    #[serde(default = "default_history_lookback_records")]
+    history_lookback_records: usize,
+    /// Only read history records from the last this many days at startup. 0
+    /// (default) doesn't limit by age.
+    #[serde(default)]
+    history_lookback_days: u64,
     port: u16,
     skip_directories: Vec<String>,
     uid: u32,
@@ -98,13 +186,25 @@ pub struct Config {
     /// is non-empty.
     #[serde(default = "default_watch_folder_interval_secs")]
     watch_folder_interval_secs: u64,
+    /// Rotate the Transmission session id (CSRF token) after this many
+    /// seconds. Clients transparently pick up the new id through a 409 retry.
+    /// 0 (default) keeps one id for the lifetime of the process.
+    #[serde(default)]
+    session_id_rotation_secs: u64,
+    /// Local directories to watch for `.torrent` and `.magnet` files dropped
+    /// by a Torrent Blackhole download client. Files dropped into a
+    /// subfolder are added with the subfolder's name as category. Scanned
+    /// every `polling_interval`. Empty (default) disables this.
+    #[serde(default)]
+    blackhole_directories: Vec<String>,
     putio: PutioConfig,
     sonarr: Option<ArrConfig>,
     radarr: Option<ArrConfig>,
     whisparr: Option<ArrConfig>,
     lidarr: Option<ArrConfig>,
+    readarr: Option<ArrConfig>,
     /// Arbitrarily-named *arr instances, configured under `[arrs.<name>]`
-    /// in config.toml. Each entry must set `type = "sonarr"|"radarr"|"whisparr"|"lidarr"`
+    /// in config.toml. Each entry must set `type = "sonarr"|"radarr"|"whisparr"|"lidarr"|"readarr"`
     /// so we know which API flavor and media type to use.
     #[serde(default)]
     arrs: HashMap<String, ArrConfig>,
@@ -112,8 +212,8 @@ pub struct Config {
 
 impl Config {
     /// Iterate over every configured *arr instance as `(name, kind, &ArrConfig)`.
-    /// Combines the named `[sonarr]`, `[radarr]`, `[whisparr]`, `[lidarr]`
-    /// sections with anything under `[arrs.*]`.
+    /// Combines the named `[sonarr]`, `[radarr]`, `[whisparr]`, `[lidarr]`,
+    /// `[readarr]` sections with anything under `[arrs.*]`.
     pub fn all_arrs(&self) -> Vec<(String, services::arr::ArrKind, &ArrConfig)> {
         let mut out: Vec<(String, services::arr::ArrKind, &ArrConfig)> = Vec::new();
         if let Some(c) = &self.sonarr {
@@ -128,6 +228,9 @@ impl Config {
         if let Some(c) = &self.lidarr {
             out.push(("lidarr".to_string(), services::arr::ArrKind::Lidarr, c));
         }
+        if let Some(c) = &self.readarr {
+            out.push(("readarr".to_string(), services::arr::ArrKind::Readarr, c));
+        }
         for (name, c) in &self.arrs {
             let kind = c
                 .r#type
@@ -139,6 +242,14 @@ impl Config {
         }
         out
     }
+
+    /// True if `category` belongs to a Readarr, so its transfers' books are
+    /// downloaded too.
+    pub fn is_book_category(&self, category: &str) -> bool {
+        self.all_arrs().iter().any(|(_, kind, arr)| {
+            *kind == services::arr::ArrKind::Readarr && arr.category.as_deref() == Some(category)
+        })
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -152,10 +263,17 @@ pub struct ArrConfig {
     pub api_key: String,
     pub category: Option<String>,
     /// For [arrs.<name>] entries: explicitly choose the *arr flavor.
-    /// One of "sonarr", "radarr", "whisparr", "lidarr". Defaults to inferring
+    /// One of "sonarr", "radarr", "whisparr", "lidarr", "readarr". Defaults to inferring
     /// from the section name, or Sonarr if that fails.
     #[serde(default, rename = "type")]
     pub r#type: Option<String>,
+    /// Download priority of this *arr's category. Transfers of a category
+    /// with a higher priority are downloaded first. Default 0.
+    #[serde(default)]
+    pub priority: i64,
+    /// Hook command for this *arr's category, instead of the global one.
+    #[serde(default)]
+    pub hook_command: Option<String>,
 }
 
 pub struct AppData {
@@ -164,6 +282,18 @@ pub struct AppData {
     /// Shared HTTP client, reused across all downloads so connections are
     /// pooled instead of building a new client per fetch.
     pub http: reqwest::Client,
+    /// Transmission CSRF token handed out to and required from RPC clients.
+    pub session_id: http::session::SessionId,
+    /// Login cookie of the qBittorrent API.
+    pub qbittorrent_sid: http::session::SessionId,
+    /// Login cookie of the Deluge JSON-RPC API.
+    pub deluge_session: http::session::SessionId,
+    /// Transfers in the download system, persisted next to the config file.
+    pub queue: download_system::queue::Queue,
+    /// Download targets waiting for a download worker.
+    pub download_queue: download_system::priority::DownloadQueue,
+    /// Imports found in the *arrs' history.
+    pub history: download_system::history::ArrHistory,
 }
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -191,6 +321,8 @@ async fn main() -> Result<()> {
                 .merge(Toml::file(&args.config_path))
                 .extract()?;
 
+            download_system::schedule::validate(&config.schedule)?;
+
             let log_timestamp = if in_container::in_container() {
                 Some(TimestampPrecision::Seconds)
             } else if let Ok(istty) = nix::unistd::isatty(0) {
@@ -227,7 +359,20 @@ async fn main() -> Result<()> {
             let app_data = web::Data::new(AppData {
                 config: config.clone(),
                 state: state::StateManager::new(config.putio.api_key.clone()),
+                history: download_system::history::ArrHistory::new(&config, http.clone()),
                 http,
+                session_id: http::session::SessionId::new(
+                    (config.session_id_rotation_secs > 0)
+                        .then(|| Duration::from_secs(config.session_id_rotation_secs)),
+                ),
+                qbittorrent_sid: http::session::SessionId::new(None),
+                deluge_session: http::session::SessionId::new(None),
+                queue: download_system::queue::Queue::load(
+                    std::path::Path::new(&args.config_path).with_file_name("queue.json"),
+                ),
+                download_queue: download_system::priority::DownloadQueue::new(
+                    config.smallest_first,
+                ),
             });
 
             match putio::account_info(&app_data.config.putio.api_key).await {
@@ -243,6 +388,10 @@ async fn main() -> Result<()> {
             // routing transfers to the correct directories.
             app_data.state.load().await?;
 
+            let download_limit = app_data.state.download_limit();
+            download_limit.set_limit(config.speed_limit_down);
+            download_limit.set_enabled(config.speed_limit_down_enabled);
+
             let data_for_download_system = app_data.clone();
             download_system::start(data_for_download_system)
                 .await
@@ -260,6 +409,9 @@ async fn main() -> Result<()> {
                     .app_data(app_data.clone())
                     .service(routes::rpc_post)
                     .service(routes::rpc_get)
+                    .configure(qbittorrent::configure)
+                    .service(deluge::json_rpc)
+                    .service(webhook::webhook)
             })
             .bind((config.bind_address, config.port))?
             .run()
@@ -3,6 +3,12 @@ use anyhow::{bail, Result};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fmt;
+use std::time::Duration;
+
+/// How long a history request may take. The shared client only limits how
+/// long connecting may take, and an *arr that accepts the connection but
+/// never answers would hold up the history watcher forever.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -14,7 +20,15 @@ pub struct ArrHistoryResponse {
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ArrHistoryRecord {
+    #[serde(default)]
+    pub id: i64,
+    /// When the event happened, as an RFC 3339 timestamp.
+    #[serde(default)]
+    pub date: Option<String>,
     pub event_type: String,
+    /// Hash of the download the record belongs to, if any.
+    #[serde(default)]
+    pub download_id: Option<String>,
     pub data: HashMap<String, Option<String>>,
 }
 
@@ -24,6 +38,7 @@ pub enum ArrKind {
     Radarr,
     Whisparr,
     Lidarr,
+    Readarr,
 }
 
 impl ArrKind {
@@ -33,16 +48,33 @@ impl ArrKind {
             "radarr" => Some(Self::Radarr),
             "whisparr" => Some(Self::Whisparr),
             "lidarr" => Some(Self::Lidarr),
+            "readarr" => Some(Self::Readarr),
             _ => None,
         }
     }
 
+    /// The history event recorded for each imported file.
+    pub fn import_event(&self) -> &'static str {
+        match self {
+            Self::Lidarr => "trackFileImported",
+            Self::Readarr => "bookFileImported",
+            _ => "downloadFolderImported",
+        }
+    }
+
     pub fn media_type(&self) -> MediaType {
         match self {
             Self::Lidarr => MediaType::Audio,
+            Self::Readarr => MediaType::Book,
             _ => MediaType::Video,
         }
     }
+
+    /// True if this *arr imports files of media type `mt`. Readarr also
+    /// imports audiobooks that put.io lists as plain audio.
+    pub fn handles(&self, mt: MediaType) -> bool {
+        mt == self.media_type() || (*self == Self::Readarr && mt == MediaType::Audio)
+    }
 }
 
 impl fmt::Display for ArrKind {
@@ -52,6 +84,7 @@ impl fmt::Display for ArrKind {
             Self::Radarr => "radarr",
             Self::Whisparr => "whisparr",
             Self::Lidarr => "lidarr",
+            Self::Readarr => "readarr",
         };
         write!(f, "{}", s)
     }
@@ -60,82 +93,59 @@ impl fmt::Display for ArrKind {
 pub struct ArrApp {
     pub name: String,
     pub kind: ArrKind,
+    pub category: Option<String>,
     base_url: String,
     api_key: String,
+    client: reqwest::Client,
 }
 
 impl ArrApp {
-    pub fn new(name: String, kind: ArrKind, config: &ArrConfig) -> Self {
+    pub fn new(name: String, kind: ArrKind, config: &ArrConfig, client: reqwest::Client) -> Self {
         Self {
             name,
             kind,
+            category: config.category.clone(),
             base_url: config.url.trim_end_matches('/').to_string(),
             api_key: config.api_key.clone(),
+            client,
         }
     }
 
-    fn history_url(&self, page: u32) -> String {
+    fn history_url(&self, page: u32, page_size: u32) -> String {
         match self.kind {
+            ArrKind::Readarr => format!(
+                "{}/api/v1/history?includeAuthor=false&includeBook=false&sortKey=date&sortDirection=descending&page={}&pageSize={}",
+                self.base_url, page, page_size
+            ),
             ArrKind::Lidarr => format!(
-                "{}/api/v1/history?includeArtist=false&includeAlbum=false&includeTrack=false&page={}&pageSize=1000",
-                self.base_url, page
+                "{}/api/v1/history?includeArtist=false&includeAlbum=false&includeTrack=false&sortKey=date&sortDirection=descending&page={}&pageSize={}",
+                self.base_url, page, page_size
             ),
             _ => format!(
-                "{}/api/v3/history?includeSeries=false&includeEpisode=false&page={}&pageSize=1000",
-                self.base_url, page
+                "{}/api/v3/history?includeSeries=false&includeEpisode=false&sortKey=date&sortDirection=descending&page={}&pageSize={}",
+                self.base_url, page, page_size
             ),
         }
     }
 
-    fn import_event(&self) -> &'static str {
-        match self.kind {
-            ArrKind::Lidarr => "trackFileImported",
-            _ => "downloadFolderImported",
+    /// Fetches a page (1-based) of the history, newest records first.
+    pub async fn history_page(&self, page: u32, page_size: u32) -> Result<ArrHistoryResponse> {
+        let url = self.history_url(page, page_size);
+        let response = self
+            .client
+            .get(&url)
+            .header("X-Api-Key", &self.api_key)
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            bail!("url: {}, status: {}", url, status);
         }
-    }
-
-    pub async fn check_imported(&self, target: &str) -> Result<bool> {
-        let client = reqwest::Client::new();
-        let mut inspected = 0;
-        let mut page = 0;
-        let import_event = self.import_event();
-        loop {
-            let url = self.history_url(page);
-            let response = client
-                .get(&url)
-                .header("X-Api-Key", &self.api_key)
-                .send()
-                .await?;
-            let status = response.status();
-            if !status.is_success() {
-                bail!("url: {}, status: {}", url, status);
-            }
-            let bytes = response.bytes().await?;
-            let json: serde_json::Result<ArrHistoryResponse> = serde_json::from_slice(&bytes);
-            if json.is_err() {
-                bail!("url: {url}, status: {status}, body: {bytes:?}");
-            }
-            let history_response: ArrHistoryResponse = json?;
-
-            for record in history_response.records {
-                if record.event_type == import_event
-                    && record
-                        .data
-                        .get("droppedPath")
-                        .and_then(|v| v.as_ref())
-                        .map(|p| p == target)
-                        .unwrap_or(false)
-                {
-                    return Ok(true);
-                }
-                inspected += 1;
-            }
-
-            if history_response.total_records < inspected {
-                page += 1;
-            } else {
-                return Ok(false);
-            }
+        let bytes = response.bytes().await?;
+        match serde_json::from_slice(&bytes) {
+            Ok(r) => Ok(r),
+            Err(_) => bail!("url: {url}, status: {status}, body: {bytes:?}"),
         }
     }
 }
@@ -32,6 +32,8 @@ pub struct PutIOTransfer {
     pub error_message: Option<String>,
     pub file_id: Option<i64>,
     pub userfile_exists: bool,
+    /// put.io's own (cloud-side) seeding rate in bytes/s.
+    pub up_speed: Option<i64>,
 }
 
 impl PutIOTransfer {
@@ -205,6 +207,9 @@ pub struct FileResponse {
     pub file_type: String,
     #[serde(default)]
     pub size: i64,
+    /// CRC32 of the file contents as 8 hex digits. Not set for folders.
+    #[serde(default)]
+    pub crc32: Option<String>,
 }
 
 pub async fn list_files(api_token: &str, file_id: i64) -> Result<ListFileResponse> {
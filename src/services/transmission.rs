@@ -1,20 +1,256 @@
+use anyhow::{bail, Context, Result};
+use base64::Engine;
 use chrono::prelude::*;
 use log::warn;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::cmp::max;
 
 use super::putio::PutIOTransfer;
 
+/// Where `queue-move-*` moves a transfer.
+#[derive(Debug, Clone, Copy)]
+pub enum QueueMove {
+    Top,
+    Up,
+    Down,
+    Bottom,
+}
+
 #[derive(Serialize, Debug)]
 pub struct TransmissionResponse {
     pub result: String,
     pub arguments: Option<serde_json::Value>,
+    /// Echo of the request's `tag`, which clients use to correlate responses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<serde_json::Value>,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct TransmissionRequest {
     pub method: String,
     pub arguments: Option<serde_json::Value>,
+    #[serde(default)]
+    pub tag: Option<serde_json::Value>,
+}
+
+impl TransmissionRequest {
+    /// Validates the method name and its arguments. The error message is
+    /// meant to be sent back to the client as the response's `result`, the
+    /// way Transmission reports failures.
+    pub fn parse(&self) -> Result<TransmissionCall> {
+        let call = match self.method.as_str() {
+            "session-get" => TransmissionCall::SessionGet,
+            "session-set" => TransmissionCall::SessionSet(self.arguments()?),
+            "session-stats" => TransmissionCall::SessionStats,
+            "free-space" => TransmissionCall::FreeSpace(self.arguments()?),
+            "torrent-get" => TransmissionCall::TorrentGet(self.arguments()?),
+            "torrent-set" => TransmissionCall::TorrentSet(self.arguments()?),
+            "queue-move-top" => TransmissionCall::QueueMove(QueueMove::Top, self.arguments()?),
+            "queue-move-up" => TransmissionCall::QueueMove(QueueMove::Up, self.arguments()?),
+            "queue-move-down" => TransmissionCall::QueueMove(QueueMove::Down, self.arguments()?),
+            "queue-move-bottom" => {
+                TransmissionCall::QueueMove(QueueMove::Bottom, self.arguments()?)
+            }
+            "torrent-start" | "torrent-start-now" => {
+                TransmissionCall::TorrentStart(self.arguments()?)
+            }
+            "torrent-stop" => TransmissionCall::TorrentStop(self.arguments()?),
+            "torrent-verify" => TransmissionCall::TorrentVerify(self.arguments()?),
+            "torrent-remove" => TransmissionCall::TorrentRemove(self.arguments()?),
+            "torrent-add" => {
+                TransmissionCall::TorrentAdd(self.arguments::<TorrentAddArguments>()?.try_into()?)
+            }
+            _ => bail!("method name not recognized: {}", self.method),
+        };
+        Ok(call)
+    }
+
+    fn arguments<T: DeserializeOwned>(&self) -> Result<T> {
+        let arguments = self
+            .arguments
+            .clone()
+            .unwrap_or_else(|| serde_json::Value::Object(Default::default()));
+        serde_json::from_value(arguments)
+            .with_context(|| format!("invalid arguments for {}", self.method))
+    }
+}
+
+/// A validated Transmission RPC call.
+#[derive(Debug)]
+pub enum TransmissionCall {
+    SessionGet,
+    SessionSet(SessionSetArguments),
+    SessionStats,
+    FreeSpace(FreeSpaceArguments),
+    TorrentGet(TorrentGetArguments),
+    TorrentSet(TorrentSetArguments),
+    QueueMove(QueueMove, TorrentActionArguments),
+    TorrentStart(TorrentActionArguments),
+    TorrentStop(TorrentActionArguments),
+    TorrentVerify(TorrentActionArguments),
+    TorrentRemove(TorrentRemoveArguments),
+    TorrentAdd(TorrentAdd),
+}
+
+/// The `session-set` settings putioarr honours; anything else is ignored.
+#[derive(Deserialize, Debug)]
+pub struct SessionSetArguments {
+    #[serde(rename = "speed-limit-down")]
+    pub speed_limit_down: Option<u64>,
+    #[serde(rename = "speed-limit-down-enabled")]
+    pub speed_limit_down_enabled: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FreeSpaceArguments {
+    /// Directory to check. Defaults to the download directory.
+    pub path: Option<String>,
+}
+
+/// A single torrent identifier: Transmission accepts both numeric ids and
+/// info hashes.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum TorrentId {
+    Id(u64),
+    Hash(String),
+}
+
+/// The `ids` argument: a single id, a list of ids, or "recently-active".
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum TorrentIds {
+    List(Vec<TorrentId>),
+    Single(TorrentId),
+}
+
+impl TorrentIds {
+    /// True for a torrent with this id or hash. `"recently-active"` matches
+    /// none; see [`Self::is_recently_active`].
+    pub fn matches(&self, id: u64, hash: Option<&str>) -> bool {
+        let matches_one = |t: &TorrentId| match t {
+            TorrentId::Id(i) => *i == id,
+            TorrentId::Hash(h) => hash.is_some_and(|hash| hash.eq_ignore_ascii_case(h)),
+        };
+        match self {
+            Self::List(ids) => ids.iter().any(matches_one),
+            Self::Single(t) => matches_one(t),
+        }
+    }
+
+    /// The hashes among the ids.
+    pub fn hashes(&self) -> Vec<&str> {
+        let ids = match self {
+            Self::List(ids) => ids.as_slice(),
+            Self::Single(id) => std::slice::from_ref(id),
+        };
+        ids.iter()
+            .filter_map(|id| match id {
+                TorrentId::Hash(h) => Some(h.as_str()),
+                TorrentId::Id(_) => None,
+            })
+            .collect()
+    }
+
+    /// True for Transmission's `"recently-active"`. putioarr has no notion of
+    /// recent activity, so `torrent-get` treats it as "all"; calls that change
+    /// or remove torrents must not.
+    pub fn is_recently_active(&self) -> bool {
+        matches!(self, Self::Single(TorrentId::Hash(h)) if h == "recently-active")
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct TorrentGetArguments {
+    /// Torrent fields to return. Empty means all of them.
+    #[serde(default)]
+    pub fields: Vec<String>,
+    /// Restricts the result to these torrents. Absent means all of them.
+    pub ids: Option<TorrentIds>,
+}
+
+impl TorrentGetArguments {
+    pub fn wants(&self, field: &str) -> bool {
+        self.fields.is_empty() || self.fields.iter().any(|f| f == field)
+    }
+}
+
+/// Arguments of the calls that only act on a set of torrents
+/// (`torrent-start`, `torrent-stop`, `torrent-verify`, `queue-move-*`).
+#[derive(Deserialize, Debug)]
+pub struct TorrentActionArguments {
+    /// Absent means all torrents.
+    pub ids: Option<TorrentIds>,
+}
+
+/// The `torrent-set` settings putioarr honours; anything else is ignored.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TorrentSetArguments {
+    /// Torrents to change. Absent means all of them.
+    pub ids: Option<TorrentIds>,
+    /// Download speed limit in kB/s.
+    pub download_limit: Option<u64>,
+    pub download_limited: Option<bool>,
+    /// -1 (low), 0 (normal) or 1 (high). Higher priorities download first.
+    pub bandwidth_priority: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TorrentRemoveArguments {
+    pub ids: TorrentIds,
+    #[serde(default, rename = "delete-local-data")]
+    pub delete_local_data: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct TorrentAddArguments {
+    filename: Option<String>,
+    metainfo: Option<String>,
+    #[serde(rename = "download-dir")]
+    download_dir: Option<String>,
+    #[serde(default)]
+    labels: Vec<String>,
+}
+
+/// What a `torrent-add` call asks us to add.
+#[derive(Debug)]
+pub enum TorrentSource {
+    /// Raw .torrent file contents.
+    Metainfo(Vec<u8>),
+    /// A magnet link or a URL pointing to a .torrent file.
+    Url(String),
+}
+
+#[derive(Debug)]
+pub struct TorrentAdd {
+    pub source: TorrentSource,
+    pub download_dir: Option<String>,
+    /// Sonarr/Radarr send their category as a label.
+    pub labels: Vec<String>,
+}
+
+impl TryFrom<TorrentAddArguments> for TorrentAdd {
+    type Error = anyhow::Error;
+
+    fn try_from(args: TorrentAddArguments) -> Result<Self> {
+        let source = match (args.metainfo, args.filename) {
+            (Some(b64), _) => {
+                let b64: String = b64.chars().filter(|c| !c.is_whitespace()).collect();
+                let bytes = base64::engine::general_purpose::STANDARD
+                    .decode(b64)
+                    .context("invalid or corrupt torrent file")?;
+                TorrentSource::Metainfo(bytes)
+            }
+            (None, Some(url)) => TorrentSource::Url(url),
+            (None, None) => bail!("no filename or metainfo specified"),
+        };
+        Ok(Self {
+            source,
+            download_dir: args.download_dir,
+            labels: args.labels,
+        })
+    }
 }
 
 #[derive(Serialize, Debug)]
@@ -25,6 +261,13 @@ pub struct TransmissionConfig {
     pub version: String,
     #[serde(rename(serialize = "download-dir"))]
     pub download_dir: String,
+    /// Bytes available in `download_dir`, -1 if it couldn't be determined.
+    #[serde(rename(serialize = "download-dir-free-space"))]
+    pub download_dir_free_space: i64,
+    #[serde(rename(serialize = "speed-limit-down"))]
+    pub speed_limit_down: u64,
+    #[serde(rename(serialize = "speed-limit-down-enabled"))]
+    pub speed_limit_down_enabled: bool,
     #[serde(rename(serialize = "seedRatioLimit"))]
     pub seed_ratio_limit: f32,
     #[serde(rename(serialize = "seedRatioLimited"))]
@@ -41,6 +284,9 @@ impl Default for TransmissionConfig {
             rpc_version: String::from("18"),
             version: String::from("14.0.0"),
             download_dir: String::from("/"),
+            download_dir_free_space: -1,
+            speed_limit_down: 100,
+            speed_limit_down_enabled: false,
             seed_ratio_limit: 1.0,
             seed_ratio_limited: true,
             idle_seeding_limit: 100,
@@ -49,6 +295,38 @@ impl Default for TransmissionConfig {
     }
 }
 
+#[derive(Serialize, Debug)]
+pub struct TransmissionFreeSpace {
+    pub path: String,
+    #[serde(rename = "size-bytes")]
+    pub size_bytes: u64,
+    pub total_size: u64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TransmissionSessionStats {
+    pub active_torrent_count: u64,
+    pub download_speed: u64,
+    pub paused_torrent_count: u64,
+    pub torrent_count: u64,
+    pub upload_speed: u64,
+    #[serde(rename = "cumulative-stats")]
+    pub cumulative_stats: TransmissionStats,
+    #[serde(rename = "current-stats")]
+    pub current_stats: TransmissionStats,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TransmissionStats {
+    pub uploaded_bytes: u64,
+    pub downloaded_bytes: u64,
+    pub files_added: u64,
+    pub session_count: u64,
+    pub seconds_active: u64,
+}
+
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct TransmissionTorrent {
@@ -69,6 +347,10 @@ pub struct TransmissionTorrent {
     pub seed_idle_limit: u64,
     pub seed_idle_mode: u32,
     pub file_count: u32,
+    pub labels: Vec<String>,
+    pub download_limit: u64,
+    pub download_limited: bool,
+    pub bandwidth_priority: i64,
 }
 
 impl From<PutIOTransfer> for TransmissionTorrent {
@@ -103,6 +385,10 @@ impl From<PutIOTransfer> for TransmissionTorrent {
             seed_idle_limit: 0,
             seed_idle_mode: 0,
             file_count: 1,
+            labels: Vec::new(),
+            download_limit: 100,
+            download_limited: false,
+            bandwidth_priority: 0,
         }
     }
 }
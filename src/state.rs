@@ -1,21 +1,33 @@
-use crate::services::putio;
+use crate::{
+    download_system::{ratelimit::RateLimiter, transfer::DownloadTarget},
+    services::putio,
+};
 use anyhow::Result;
 use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use tokio::sync::RwLock;
+use tokio::sync::{Notify, RwLock};
 
 /// Key under which putioarr stores its transfer state in put.io's per-user
 /// key-value config store.
 const CONFIG_KEY: &str = "putioarr_transfers";
 
+/// Key under which categories created through the qBittorrent API and Deluge
+/// labels are stored, as a map of category name to save path.
+const CATEGORIES_CONFIG_KEY: &str = "putioarr_categories";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransferState {
     pub hash: String,
     pub source_category: String,
     pub download_dir: String,
+    /// Transmission labels the transfer was added with, reported back in
+    /// `torrent-get`.
+    #[serde(default)]
+    pub labels: Vec<String>,
 }
 
 /// A completed file found in a `watch_folders` folder that has no transfer
@@ -30,6 +42,40 @@ pub struct OrphanFile {
     pub download_dir: String,
 }
 
+/// Disk space a transfer was given to download into: whatever its targets
+/// still need in `dir`.
+#[derive(Debug, Clone)]
+pub struct SpaceReservation {
+    pub dir: String,
+    pub targets: Vec<DownloadTarget>,
+}
+
+#[derive(Default)]
+struct SpaceReservations {
+    /// Bumped on every change, so a transfer can tell whether the
+    /// reservations changed while it measured them.
+    generation: u64,
+    by_transfer: HashMap<u64, SpaceReservation>,
+}
+
+/// Snapshot of putioarr's local download counters, reported through the
+/// Transmission `session-stats` call.
+#[derive(Debug, Clone, Copy)]
+pub struct DownloadStats {
+    pub downloaded_bytes: u64,
+    pub files_downloaded: u64,
+    pub seconds_active: u64,
+    /// Bytes/s pulled from put.io to local disk, averaged since the last sample.
+    pub download_speed: u64,
+}
+
+/// Last point at which the local download speed was sampled.
+struct SpeedSample {
+    at: Instant,
+    bytes: u64,
+    speed: u64,
+}
+
 /// Tracks the category/download-dir chosen for each transfer.
 ///
 /// Reads are served from an in-memory cache for speed, while mutations are
@@ -39,6 +85,9 @@ pub struct OrphanFile {
 pub struct StateManager {
     api_token: String,
     transfers: Arc<RwLock<HashMap<String, TransferState>>>,
+    /// Categories created by a client (qBittorrent `createCategory`, Deluge
+    /// `label.add`), mapped to their save path. Persisted next to the transfers.
+    categories: Arc<RwLock<HashMap<String, String>>>,
     /// Transfer ids whose files putioarr has finished downloading to local
     /// disk. Used to avoid telling the *arr a download is complete before the
     /// files actually exist locally (see issue #16).
@@ -62,6 +111,49 @@ pub struct StateManager {
     /// the log. A misconfigured Sonarr/Radarr fails on every poll for every
     /// transfer, and logging each one filled users' disks over time (issue #21).
     arr_error_logged: Arc<RwLock<HashMap<String, Instant>>>,
+    /// Hashes of transfers stopped through `torrent-stop`. Their targets are
+    /// not downloaded until a `torrent-start`. Kept in memory only, so a
+    /// restart resumes everything.
+    stopped: Arc<RwLock<HashSet<String>>>,
+    /// Transfer ids an orchestration worker is currently downloading.
+    processing: Arc<RwLock<HashSet<u64>>>,
+    /// Transfer ids currently being watched for import, so a re-download
+    /// doesn't start a second watcher for the same transfer.
+    watching: Arc<RwLock<HashSet<u64>>>,
+    /// Transfer ids to hand to the orchestration workers again on the next
+    /// poll (after a `torrent-start` or a failed `torrent-verify`).
+    requeue: Arc<RwLock<HashSet<u64>>>,
+    /// Why a transfer is held back until there's enough free disk space, by
+    /// transfer id. Reported to the *arr as the torrent's error.
+    waiting_for_space: Arc<RwLock<HashMap<u64, String>>>,
+    /// Transfers that were given disk space to download into, by transfer
+    /// id, so transfers starting at the same time don't count on the same
+    /// free space.
+    space_reservations: Arc<Mutex<SpaceReservations>>,
+    /// Total bytes written to local disk by the download workers this session.
+    downloaded_bytes: Arc<AtomicU64>,
+    /// Number of files fully downloaded this session.
+    files_downloaded: Arc<AtomicU64>,
+    started_at: Instant,
+    speed_sample: Arc<Mutex<SpeedSample>>,
+    /// Global download speed limit (`speed-limit-down`), shared by every
+    /// download worker.
+    download_limit: Arc<RateLimiter>,
+    /// Per-transfer download speed limits set through `torrent-set`, keyed
+    /// by lowercase hash. In memory only.
+    transfer_limits: Arc<RwLock<HashMap<String, Arc<RateLimiter>>>>,
+    /// Download speed limit of the currently active schedule window.
+    schedule_limit: Arc<RateLimiter>,
+    /// True while a schedule window pauses all downloads.
+    schedule_paused: Arc<AtomicBool>,
+    /// Imports reported through the webhook, by lowercase hash, with the
+    /// names of the files imported so far (empty if the *arr didn't say).
+    webhook_imports: Arc<RwLock<HashMap<String, HashSet<String>>>>,
+    /// Wakes the import watchers when a webhook arrives.
+    webhook_notify: Arc<Notify>,
+    /// Categories an *arr sent a webhook for. Their *arr's history is polled
+    /// less often from then on.
+    webhook_categories: Arc<RwLock<HashSet<String>>>,
 }
 
 impl StateManager {
@@ -69,11 +161,129 @@ impl StateManager {
         Self {
             api_token,
             transfers: Arc::new(RwLock::new(HashMap::new())),
+            categories: Arc::new(RwLock::new(HashMap::new())),
             local_complete: Arc::new(RwLock::new(HashSet::new())),
             file_names: Arc::new(RwLock::new(HashMap::new())),
             failed_names: Arc::new(RwLock::new(HashMap::new())),
             orphans: Arc::new(RwLock::new(HashMap::new())),
             arr_error_logged: Arc::new(RwLock::new(HashMap::new())),
+            stopped: Arc::new(RwLock::new(HashSet::new())),
+            processing: Arc::new(RwLock::new(HashSet::new())),
+            watching: Arc::new(RwLock::new(HashSet::new())),
+            requeue: Arc::new(RwLock::new(HashSet::new())),
+            waiting_for_space: Arc::new(RwLock::new(HashMap::new())),
+            space_reservations: Arc::new(Mutex::new(SpaceReservations::default())),
+            downloaded_bytes: Arc::new(AtomicU64::new(0)),
+            files_downloaded: Arc::new(AtomicU64::new(0)),
+            started_at: Instant::now(),
+            speed_sample: Arc::new(Mutex::new(SpeedSample {
+                at: Instant::now(),
+                bytes: 0,
+                speed: 0,
+            })),
+            download_limit: Arc::new(RateLimiter::new(0, false)),
+            transfer_limits: Arc::new(RwLock::new(HashMap::new())),
+            schedule_limit: Arc::new(RateLimiter::new(0, false)),
+            schedule_paused: Arc::new(AtomicBool::new(false)),
+            webhook_imports: Arc::new(RwLock::new(HashMap::new())),
+            webhook_notify: Arc::new(Notify::new()),
+            webhook_categories: Arc::new(RwLock::new(HashSet::new())),
+        }
+    }
+
+    /// Minimum time between two download speed samples.
+    const SPEED_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// Adds `bytes` to the local download counter. Called by the download
+    /// workers for every chunk written to disk.
+    pub fn record_downloaded_bytes(&self, bytes: u64) {
+        self.downloaded_bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.sample_speed();
+    }
+
+    /// Counts a file that finished downloading locally.
+    pub fn record_file_downloaded(&self) {
+        self.files_downloaded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Re-computes the download speed if the last sample is old enough and
+    /// returns the current value.
+    fn sample_speed(&self) -> u64 {
+        let mut sample = self.speed_sample.lock().unwrap();
+        let elapsed = sample.at.elapsed();
+        if elapsed >= Self::SPEED_SAMPLE_INTERVAL {
+            let bytes = self.downloaded_bytes.load(Ordering::Relaxed);
+            sample.speed =
+                (bytes.saturating_sub(sample.bytes) as f64 / elapsed.as_secs_f64()) as u64;
+            sample.bytes = bytes;
+            sample.at = Instant::now();
+        }
+        sample.speed
+    }
+
+    /// Returns a snapshot of the local download counters.
+    pub fn download_stats(&self) -> DownloadStats {
+        DownloadStats {
+            downloaded_bytes: self.downloaded_bytes.load(Ordering::Relaxed),
+            files_downloaded: self.files_downloaded.load(Ordering::Relaxed),
+            seconds_active: self.started_at.elapsed().as_secs(),
+            download_speed: self.sample_speed(),
+        }
+    }
+
+    /// The global download speed limit.
+    pub fn download_limit(&self) -> &RateLimiter {
+        &self.download_limit
+    }
+
+    /// The download speed limit of the transfer with this hash, if one was set.
+    pub async fn transfer_download_limit(&self, hash: &str) -> Option<Arc<RateLimiter>> {
+        self.transfer_limits.read().await.get(&hash.to_lowercase()).cloned()
+    }
+
+    /// Updates the download speed limit (kB/s) and/or whether it's enforced
+    /// for the transfer with this hash.
+    pub async fn set_transfer_download_limit(
+        &self,
+        hash: &str,
+        limit: Option<u64>,
+        enabled: Option<bool>,
+    ) {
+        let mut limits = self.transfer_limits.write().await;
+        let limiter = limits
+            .entry(hash.to_lowercase())
+            .or_insert_with(|| Arc::new(RateLimiter::new(0, false)));
+        if let Some(limit) = limit {
+            limiter.set_limit(limit);
+        }
+        if let Some(enabled) = enabled {
+            limiter.set_enabled(enabled);
+        }
+    }
+
+    /// The download speed limit imposed by the schedule.
+    pub fn schedule_limit(&self) -> &RateLimiter {
+        &self.schedule_limit
+    }
+
+    /// True while the schedule pauses all downloads.
+    pub fn is_schedule_paused(&self) -> bool {
+        self.schedule_paused.load(Ordering::Relaxed)
+    }
+
+    /// Pauses or resumes downloads on behalf of the schedule. Returns the
+    /// previous value.
+    pub fn set_schedule_paused(&self, paused: bool) -> bool {
+        self.schedule_paused.swap(paused, Ordering::Relaxed)
+    }
+
+    /// Waits until `bytes` more may be downloaded for the transfer with this
+    /// hash, honouring the global, the scheduled and the transfer's own limit.
+    pub async fn throttle_download(&self, hash: &str, bytes: u64) {
+        self.download_limit.acquire(bytes).await;
+        self.schedule_limit.acquire(bytes).await;
+        if let Some(limiter) = self.transfer_download_limit(hash).await {
+            limiter.acquire(bytes).await;
         }
     }
 
@@ -168,6 +378,174 @@ impl StateManager {
         self.local_complete.write().await.remove(&id);
     }
 
+    /// Stops local downloading of the transfer with this hash.
+    pub async fn stop_transfer(&self, hash: &str) {
+        self.stopped.write().await.insert(hash.to_lowercase());
+    }
+
+    /// Lifts a previous [`Self::stop_transfer`]. Returns true if it was stopped.
+    pub async fn start_transfer(&self, hash: &str) -> bool {
+        self.stopped.write().await.remove(&hash.to_lowercase())
+    }
+
+    /// Drops what's kept in memory about the transfer with this hash, once
+    /// it's gone from put.io.
+    pub async fn forget_transfer(&self, hash: &str) {
+        let hash = hash.to_lowercase();
+        self.stopped.write().await.remove(&hash);
+        self.transfer_limits.write().await.remove(&hash);
+    }
+
+    /// True if the transfer with this hash was stopped through `torrent-stop`.
+    pub async fn is_stopped(&self, hash: &str) -> bool {
+        self.stopped.read().await.contains(&hash.to_lowercase())
+    }
+
+    /// Marks a transfer as being downloaded by an orchestration worker.
+    pub async fn begin_processing(&self, id: u64) {
+        self.processing.write().await.insert(id);
+    }
+
+    /// Clears the marker set by [`Self::begin_processing`].
+    pub async fn end_processing(&self, id: u64) {
+        self.processing.write().await.remove(&id);
+    }
+
+    /// Marks a transfer as watched for import. Returns false if it already was.
+    pub async fn begin_watching(&self, id: u64) -> bool {
+        self.watching.write().await.insert(id)
+    }
+
+    /// Clears the marker set by [`Self::begin_watching`].
+    pub async fn end_watching(&self, id: u64) {
+        self.watching.write().await.remove(&id);
+    }
+
+    /// Records an import an *arr reported through the webhook for a
+    /// transfer in `category` and wakes the import watchers.
+    pub async fn record_webhook_import(
+        &self,
+        hash: &str,
+        category: Option<String>,
+        files: Vec<String>,
+    ) {
+        self.webhook_imports
+            .write()
+            .await
+            .entry(hash.to_lowercase())
+            .or_default()
+            .extend(files);
+        if let Some(category) = category {
+            self.webhook_categories.write().await.insert(category);
+        }
+        self.webhook_notify.notify_waiters();
+    }
+
+    /// The files reported imported through the webhook for this hash, if any
+    /// import was reported.
+    pub async fn webhook_import(&self, hash: &str) -> Option<HashSet<String>> {
+        self.webhook_imports
+            .read()
+            .await
+            .get(&hash.to_lowercase())
+            .cloned()
+    }
+
+    /// True if an import reported through the webhook is still being
+    /// watched.
+    pub async fn has_webhook_imports(&self) -> bool {
+        !self.webhook_imports.read().await.is_empty()
+    }
+
+    /// Forgets the webhook imports of a transfer that's done.
+    pub async fn clear_webhook_import(&self, hash: &str) {
+        self.webhook_imports
+            .write()
+            .await
+            .remove(&hash.to_lowercase());
+    }
+
+    /// Waits for the next webhook import, for at most `timeout`.
+    pub async fn wait_for_webhook(&self, timeout: Duration) {
+        let _ = tokio::time::timeout(timeout, self.webhook_notify.notified()).await;
+    }
+
+    /// True once the *arr of `category` has sent a webhook this session.
+    pub async fn webhooks_received(&self, category: &str) -> bool {
+        self.webhook_categories.read().await.contains(category)
+    }
+
+    /// Marks a transfer as waiting for disk space, or clears the mark with
+    /// `None`.
+    pub async fn set_waiting_for_space(&self, id: u64, reason: Option<String>) {
+        let mut waiting = self.waiting_for_space.write().await;
+        match reason {
+            Some(reason) => waiting.insert(id, reason),
+            None => waiting.remove(&id),
+        };
+    }
+
+    /// Why the transfer is waiting for disk space, if it is.
+    pub async fn waiting_for_space(&self, id: u64) -> Option<String> {
+        self.waiting_for_space.read().await.get(&id).cloned()
+    }
+
+    /// The disk space reserved for other transfers than `id`, along with the
+    /// generation to pass to [`Self::reserve_space`].
+    pub fn space_reservations(&self, id: u64) -> (u64, Vec<SpaceReservation>) {
+        let reservations = self.space_reservations.lock().unwrap();
+        let others = reservations
+            .by_transfer
+            .iter()
+            .filter(|(other, _)| **other != id)
+            .map(|(_, r)| r.clone())
+            .collect();
+        (reservations.generation, others)
+    }
+
+    /// Reserves disk space for a transfer, unless the reservations changed
+    /// since `generation` was read. Returns false if they did, and the space
+    /// has to be measured again.
+    pub fn reserve_space(&self, generation: u64, id: u64, reservation: SpaceReservation) -> bool {
+        let mut reservations = self.space_reservations.lock().unwrap();
+        if reservations.generation != generation {
+            return false;
+        }
+        reservations.generation += 1;
+        reservations.by_transfer.insert(id, reservation);
+        true
+    }
+
+    /// Gives back the disk space reserved for a transfer.
+    pub fn release_space(&self, id: u64) {
+        let mut reservations = self.space_reservations.lock().unwrap();
+        if reservations.by_transfer.remove(&id).is_some() {
+            reservations.generation += 1;
+        }
+    }
+
+    /// Asks for a transfer to be queued for download again.
+    pub async fn request_requeue(&self, id: u64) {
+        self.requeue.write().await.insert(id);
+    }
+
+    /// Takes the transfers waiting to be queued again. Transfers that are
+    /// still being downloaded stay pending until their current run finishes,
+    /// so the same files are never fetched twice at once.
+    pub async fn take_requeue(&self) -> Vec<u64> {
+        let processing = self.processing.read().await;
+        let mut requeue = self.requeue.write().await;
+        let ready: Vec<u64> = requeue
+            .iter()
+            .filter(|id| !processing.contains(id))
+            .copied()
+            .collect();
+        for id in &ready {
+            requeue.remove(id);
+        }
+        ready
+    }
+
     /// Loads persisted state from put.io into the in-memory cache. Should be
     /// called once at startup, before any transfers are processed.
     pub async fn load(&self) -> Result<()> {
@@ -182,6 +560,37 @@ impl StateManager {
             Ok(None) => debug!("state: no persisted state found in put.io config"),
             Err(e) => warn!("state: failed to load persisted state from put.io: {}", e),
         }
+        match putio::get_config_value::<HashMap<String, String>>(
+            &self.api_token,
+            CATEGORIES_CONFIG_KEY,
+        )
+        .await
+        {
+            Ok(Some(map)) => {
+                info!("state: loaded {} categories from put.io config", map.len());
+                *self.categories.write().await = map;
+            }
+            Ok(None) => debug!("state: no persisted categories found in put.io config"),
+            Err(e) => warn!("state: failed to load persisted categories from put.io: {}", e),
+        }
+        Ok(())
+    }
+
+    /// Categories created by clients, mapped to their save path.
+    pub async fn categories(&self) -> HashMap<String, String> {
+        self.categories.read().await.clone()
+    }
+
+    /// Records a client-created category and persists it to put.io.
+    pub async fn add_category(&self, name: String, save_path: String) -> Result<()> {
+        let map = {
+            let mut categories = self.categories.write().await;
+            categories.insert(name, save_path);
+            categories.clone()
+        };
+        if let Err(e) = putio::set_config_value(&self.api_token, CATEGORIES_CONFIG_KEY, &map).await {
+            error!("state: failed to persist categories to put.io: {}", e);
+        }
         Ok(())
     }
 
@@ -198,11 +607,12 @@ impl StateManager {
         hash: String,
         category: String,
         download_dir: String,
+        labels: Vec<String>,
     ) -> Result<()> {
         let key = hash.to_lowercase();
         debug!(
-            "state: add_transfer hash={} category={} dir={}",
-            key, category, download_dir
+            "state: add_transfer hash={} category={} dir={} labels={:?}",
+            key, category, download_dir, labels
         );
         {
             let mut transfers = self.transfers.write().await;
@@ -212,6 +622,7 @@ impl StateManager {
                     hash: key,
                     source_category: category,
                     download_dir,
+                    labels,
                 },
             );
         }
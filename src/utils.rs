@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{Context as _, Result};
 use serde::Serialize;
 use std::{fs, io::Write, path::Path, time::Duration};
 use tinytemplate::TinyTemplate;
@@ -14,6 +14,14 @@ password = "mypassword"
 # sonarr/radarr in order to import downloads
 download_directory = "/path/to/downloads"
 
+# Optional. Assemble downloads here and move each one to download_directory only when it's complete,
+# like Transmission's incomplete directory. Preferably on the same filesystem, so the move is a rename.
+# incomplete_directory = "/path/to/incomplete"
+
+# Optional. Also download .zip, .tar, .tar.gz and .tgz archives and unpack them before reporting the
+# download as complete. The archives are removed once the *arr imported the contents. Default false.
+# extract_archives = false
+
 # Optional bind address, default "0.0.0.0"
 bind_address = "0.0.0.0"
 
@@ -35,6 +43,22 @@ polling_interval = 10
 # Set to 0 to disable (watch indefinitely).
 import_timeout_secs = 7200
 
+# Optional. Shell command run after a transfer finished downloading and again after it was imported.
+# It gets PUTIOARR_EVENT ("downloaded" or "imported"), PUTIOARR_NAME, PUTIOARR_HASH,
+# PUTIOARR_CATEGORY, PUTIOARR_PATH, PUTIOARR_FILE_ID and PUTIOARR_SIZE in its environment. Each *arr
+# section can set its own hook_command. It's killed after hook_timeout_secs (default 300). With
+# hook_blocks_completion, a failing hook keeps the transfer from being reported as downloaded until
+# it's stopped and started again from the *arr, or putioarr restarts.
+# hook_command = "/path/to/script.sh"
+# hook_timeout_secs = 300
+# hook_blocks_completion = false
+
+# Optional. How far back the *arrs' history is read at startup to find imports of downloads that
+# were still on put.io: at most history_lookback_records records (default 10000, 0 for all of it)
+# and, if set, only those of the last history_lookback_days days.
+# history_lookback_records = 10000
+# history_lookback_days = 30
+
 # Optional skip directories when downloading, default ["sample", "extras"]
 skip_directories = ["sample", "extras"]
 
@@ -56,6 +80,17 @@ download_unmanaged = false
 # lists every configured folder on put.io; raise it to reduce API traffic.
 # watch_folder_interval_secs = 60
 
+# Optional. Rotate the Transmission session id every this many seconds. Clients pick up the
+# new id automatically. Default 0 (one id for as long as putioarr runs).
+# session_id_rotation_secs = 0
+
+# Optional. Directories to watch for .torrent and .magnet files dropped by a Torrent Blackhole
+# download client. Files dropped into a subfolder (e.g. /path/to/blackhole/tv) get the subfolder's
+# name as category and are downloaded to that subdirectory of download_directory. Processed files
+# are moved to .processed, files that couldn't be added in three tries to .failed. Empty (default)
+# disables this.
+# blackhole_directories = ["/path/to/blackhole"]
+
 # Optional number of orchestration workers, default 10. Unless there are many changes coming from
 # put.io, you shouldn't have to touch this number. 10 is already overkill.
 orchestration_workers = 10
@@ -63,6 +98,35 @@ orchestration_workers = 10
 # Optional number of download workers, default 4. This controls how many downloads we run in parallel.
 download_workers = 4
 
+# Optional. Download large files over this many connections at once, each fetching its own part of
+# the file, default 1 (one connection per file). Raise it if a single put.io connection doesn't fill
+# your line. Every part is at least min_segment_size_mb (default 64) MiB.
+# download_segments = 1
+# min_segment_size_mb = 64
+
+# Optional. MiB to keep free on the download disk. A transfer that doesn't fit waits (shown as an error
+# in the *arr) until enough space frees up. Default 1024.
+# free_space_reserve_mb = 1024
+
+# Optional. Cap the download speed from put.io at this many kB/s across all downloads. Only applied
+# when speed_limit_down_enabled is true. Both can also be changed from a Transmission client.
+# speed_limit_down = 10000
+# speed_limit_down_enabled = false
+
+# Optional. Time windows (local time) during which downloads are slowed down or paused, like
+# Transmission's alternative speed schedule. Each window sets either a speed_limit in kB/s or
+# paused = true. days defaults to every day; a window whose end is before its start runs past
+# midnight. Repeat the [[schedule]] section for more windows.
+# [[schedule]]
+# days = ["mon", "tue", "wed", "thu", "fri"]
+# start = "17:00"
+# end = "23:30"
+# speed_limit = 2000
+
+# Optional. Queue new transfers ahead of larger ones that haven't started downloading yet, so a
+# single episode doesn't have to wait for a season pack. Default false (first come, first served).
+# smallest_first = false
+
 [putio]
 # Required. Putio API key. You can generate one using `putioarr get-token`
 api_key =  "{putio_api_key}"
@@ -75,6 +139,10 @@ api_key = "MYSONARRAPIKEY"
 # Optional category/subdirectory for Sonarr downloads (e.g., "tv" or "shows")
 # This should match the category configured in Sonarr's download client settings
 category = "tv"
+# Optional download priority of this category, default 0. Higher priorities are downloaded first.
+# priority = 1
+# Optional hook command for this category, instead of the global hook_command
+# hook_command = "/path/to/tv-script.sh"
 
 [radarr]
 url = "http://myradarrhost:7878/radarr"
@@ -99,10 +167,21 @@ api_key = "MYLIDARRAPIKEY"
 # Optional category/subdirectory for Lidarr downloads (e.g., "music")
 category = "music"
 
+# Optional. Transfers in a Readarr's category get their ebooks (epub, mobi, azw3, pdf) and
+# audiobooks downloaded too; books in other transfers are left on put.io.
+# [readarr]
+# url = "http://myreadarrhost:8787/readarr"
+# Can be found in Settings -> General
+# api_key = "MYREADARRAPIKEY"
+# Category/subdirectory for Readarr downloads (e.g., "books"). Books are only downloaded for
+# transfers in this category.
+# category = "books"
+
 # Additional *arr instances can be added under [arrs.<name>] with any name.
-# They behave identically to [sonarr]/[radarr]/[whisparr]/[lidarr]; use
+# They behave identically to [sonarr]/[radarr]/[whisparr]/[lidarr]/[readarr]; use
 # `type` to pick the flavor (defaults to inferring from the section name).
-# Useful for running multiple Sonarr/Radarr/Whisparr/Lidarr instances side by side.
+# Useful for running multiple Sonarr/Radarr/Whisparr/Lidarr/Readarr instances side by side,
+# e.g. a separate Readarr for audiobooks.
 # [arrs.whisparrv2]
 # type = "whisparr"
 # url = "http://whis.lan:6969/whisparr"
@@ -175,3 +254,28 @@ pub async fn get_token() -> Result<String> {
         };
     }
 }
+
+/// Space on the filesystem holding a path, in bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct DiskSpace {
+    /// Bytes available to unprivileged users.
+    pub available: u64,
+    pub total: u64,
+}
+
+/// Returns the disk space of the filesystem `path` lives on. A path that
+/// doesn't exist yet (e.g. a category directory nothing was downloaded to) is
+/// resolved to its nearest existing ancestor.
+pub fn disk_space(path: &str) -> Result<DiskSpace> {
+    let existing = Path::new(path)
+        .ancestors()
+        .find(|p| p.exists())
+        .with_context(|| format!("no existing directory for {}", path))?;
+    let stat = nix::sys::statvfs::statvfs(existing)
+        .with_context(|| format!("statvfs on {}", existing.display()))?;
+    let fragment_size = stat.fragment_size() as u64;
+    Ok(DiskSpace {
+        available: stat.blocks_available() as u64 * fragment_size,
+        total: stat.blocks() as u64 * fragment_size,
+    })
+}